@@ -1,15 +1,16 @@
 use std::env;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub enum Output {
-    Args(Args),
+    Args(Box<Args>),
     Info(String),
 }
 
 impl Output {
     pub fn unwrap(self) -> (Option<Args>, Option<String>) {
         match self {
-            Output::Args(args) => (Some(args), None),
+            Output::Args(args) => (Some(*args), None),
             Output::Info(info) => (None, Some(info))
         }
     }
@@ -18,68 +19,344 @@ impl Output {
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 enum Mode {
     OneShot,
-    Continuous
+    Continuous,
+    Daemon,
+    Status,
+    SystemdUnit,
+    Dispatcher,
+    InstallDispatcher
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Probe {
+    Tcp,
+    Http,
+    Nm
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Validation {
+    Strict,
+    Warn,
+    Off
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Portal {
+    Legacy,
+    Modern,
+    Auto,
+    Fon,
+    Wispr
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CaptiveApi {
+    Dhcp,
+    Url(String),
+    Off
 }
 
 #[derive(Debug)]
 pub struct Args {
-    username: String,
-    password: String,
-    mode: Mode
+    username: Option<String>,
+    password: Option<String>,
+    mode: Mode,
+    probe: Probe,
+    validation: Validation,
+    portal: Portal,
+    portal_file: Option<String>,
+    captive_api: CaptiveApi,
+    max_session: Option<Duration>,
+    relogin_margin: Duration,
+    timeout: Option<Duration>,
+    user_agent: Option<String>,
+    cookie_file: Option<String>,
+    proxy: Option<String>,
+    config: Option<String>,
+    dns: Option<String>,
+    hosts: Vec<String>,
+    trust_store: Option<String>,
+    pins: Vec<String>,
+    portal_domains: Vec<String>,
+    allowed_ouis: Vec<String>,
+    hotspot_check: Option<bool>,
+    max_login_failures: u32,
+    reset_login_limit: bool,
+    socket: Option<String>,
+    control: Option<String>,
+    pid_file: Option<String>,
+    forward: bool,
+    dispatcher: Option<(String, String)>,
+    target: String
 }
 
 impl Args {
 
-    #[allow(dead_code)]
-    pub fn user(&self) -> &str {
-        &self.username
+    /// Username and password given on the command line, if any. They can
+    /// be left out when the config file lists accounts.
+    pub fn credentials(&self) -> Option<(&str, &str)> {
+        self.username.as_deref().zip(self.password.as_deref())
     }
 
     #[allow(dead_code)]
-    pub fn username(&self) -> &str {
-        &self.username
+    pub fn is_continuous(&self) -> bool {
+        self.mode == Mode::Continuous
     }
 
     #[allow(dead_code)]
-    pub fn pass(&self) -> &str {
-        &self.password
+    pub fn is_one_shot(&self) -> bool {
+        self.mode == Mode::OneShot
     }
 
-    #[allow(dead_code)]
-    pub fn password(&self) -> &str {
-        &self.password
+    pub fn is_daemon(&self) -> bool {
+        self.mode == Mode::Daemon
     }
 
-    #[allow(dead_code)]
-    pub fn is_continuous(&self) -> bool {
-        self.mode == Mode::Continuous
+    pub fn is_systemd_unit(&self) -> bool {
+        self.mode == Mode::SystemdUnit
     }
 
-    #[allow(dead_code)]
-    pub fn is_one_shot(&self) -> bool {
-        self.mode == Mode::OneShot
+    pub fn is_status(&self) -> bool {
+        self.mode == Mode::Status
+    }
+
+    pub fn is_install_dispatcher(&self) -> bool {
+        self.mode == Mode::InstallDispatcher
+    }
+
+    /// Interface and action NetworkManager invoked the dispatcher with.
+    pub fn dispatcher(&self) -> Option<(&str, &str)> {
+        self.dispatcher.as_ref().map(|(interface, action)| (interface.as_str(), action.as_str()))
+    }
+
+    pub fn probe(&self) -> Probe {
+        self.probe
+    }
+
+    pub fn validation(&self) -> Validation {
+        self.validation
+    }
+
+    pub fn portal(&self) -> Portal {
+        self.portal
+    }
+
+    pub fn portal_file(&self) -> Option<&str> {
+        self.portal_file.as_deref()
+    }
+
+    pub fn captive_api(&self) -> &CaptiveApi {
+        &self.captive_api
+    }
+
+    pub fn max_session(&self) -> Option<Duration> {
+        self.max_session
+    }
+
+    pub fn relogin_margin(&self) -> Duration {
+        self.relogin_margin
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub fn user_agent(&self) -> Option<&str> {
+        self.user_agent.as_deref()
+    }
+
+    pub fn cookie_file(&self) -> Option<&str> {
+        self.cookie_file.as_deref()
+    }
+
+    pub fn proxy(&self) -> Option<&str> {
+        self.proxy.as_deref()
+    }
+
+    pub fn config(&self) -> Option<&str> {
+        self.config.as_deref()
+    }
+
+    pub fn dns(&self) -> Option<&str> {
+        self.dns.as_deref()
+    }
+
+    pub fn hosts(&self) -> &[String] {
+        &self.hosts
+    }
+
+    pub fn trust_store(&self) -> Option<&str> {
+        self.trust_store.as_deref()
+    }
+
+    pub fn pins(&self) -> &[String] {
+        &self.pins
+    }
+
+    pub fn portal_domains(&self) -> &[String] {
+        &self.portal_domains
+    }
+
+    pub fn allowed_ouis(&self) -> &[String] {
+        &self.allowed_ouis
+    }
+
+    /// Whether the hotspot check was turned on or off explicitly.
+    pub fn hotspot_check(&self) -> Option<bool> {
+        self.hotspot_check
+    }
+
+    pub fn max_login_failures(&self) -> u32 {
+        self.max_login_failures
+    }
+
+    pub fn reset_login_limit(&self) -> bool {
+        self.reset_login_limit
+    }
+
+    pub fn socket(&self) -> Option<&str> {
+        self.socket.as_deref()
+    }
+
+    /// Command to send to a running daemon instead of running this one.
+    pub fn control(&self) -> Option<&str> {
+        self.control.as_deref()
+    }
+
+    pub fn pid_file(&self) -> Option<&str> {
+        self.pid_file.as_deref()
+    }
+
+    pub fn forward(&self) -> bool {
+        self.forward
     }
 
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+}
+
+/// The command line `args` without credentials or the options picking a
+/// mode, to run another mode with the same settings.
+pub fn forwarded(args: &[String]) -> Vec<String> {
+    const DROPPED_FLAGS: [&str; 5] = ["-c", "--continuous", "--daemon", "--generate-systemd-unit", "--install-dispatcher"];
+    const DROPPED_OPTIONS: [&str; 4] = ["-u", "--username", "-p", "--password"];
+    let mut forwarded = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if DROPPED_OPTIONS.contains(&arg.as_str()) {
+            args.next();
+        } else if arg == "--dispatcher" {
+            args.next();
+            args.next();
+        } else if !DROPPED_FLAGS.contains(&arg.as_str()) {
+            forwarded.push(arg.clone());
+        }
+    }
+    forwarded
+}
+
+/// Whether the command line `args` asks for nothing a running daemon can't
+/// take over when forwarded to it: a target and where to reach the daemon.
+pub fn only_target(args: &[String]) -> bool {
+    const KEPT_FLAGS: [&str; 3] = ["-c", "--continuous", "--forward"];
+    const KEPT_OPTIONS: [&str; 4] = ["-t", "--target", "--socket", "--pid-file"];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if KEPT_OPTIONS.contains(&arg.as_str()) {
+            args.next();
+        } else if !KEPT_FLAGS.contains(&arg.as_str()) {
+            return false;
+        }
+    }
+    true
 }
 
 fn usage_instructions() -> String {
     format!(
-        "{}{}{}{}{}{}{}",
-        "Usage: program -u <username> -p <password> [-c]\n\n",
+        "{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}",
+        "Usage: program [-u <username> -p <password>] [-c] [-t <connection>] [--portal <legacy|modern|auto|fon|wispr>] [--portal-file <path>] [--probe <tcp|http|nm>] [--validation <strict|warn|off>] [--captive-api <dhcp|url|off>] [--max-session <seconds>] [--relogin-margin <seconds>] [--timeout <seconds>] [--user-agent <string>] [--cookie-file <path>] [--proxy <url|env|none>] [--config <path>] [--dns <system|gateway|address>] [--host <name=address>]... [--trust-store <path>] [--pin <sha256/base64>]... [--portal-domain <domain>]... [--allowed-oui <oui>]... [--hotspot-check|--no-hotspot-check] [--max-login-failures <n>] [--reset-login-limit] [--status] [--daemon] [--socket <path>] [--ctl <command>] [--pid-file <path>] [--forward] [--generate-systemd-unit] [--dispatcher <interface> <action>] [--install-dispatcher]\n\n",
         "Options:\n",
         "\t-u, --username    Specify the username\n",
-        "\t-p, --password    Specify the password\n",
+        "\t-p, --password    Specify the password (both optional when the config lists accounts)\n",
         "\t-c, --continuous  Run in continuous mode (default is one-shot)\n",
+        "\t-t, --target      NetworkManager connection to log in on (default is MEO-WiFi)\n",
+        "\t    --portal      Portal login flow (default is legacy)\n",
+        "\t    --portal-file Log in with a portal definition file instead\n",
+        "\t    --probe       Connectivity check used in continuous mode: tcp, http or nm (default is tcp)\n",
+        "\t    --validation  How strictly credentials are checked (default is strict)\n",
+        "\t    --captive-api Captive Portal API used in continuous mode (default is dhcp)\n",
+        "\t    --max-session Session length assumed when the portal does not report it\n",
+        "\t    --relogin-margin Seconds before expiry to log in again (default is 60)\n",
+        "\t    --timeout     Request timeout in seconds for the legacy portal (default is 2)\n",
+        "\t    --user-agent  User-Agent sent to the legacy portal\n",
+        "\t    --cookie-file Keep legacy portal cookies in this file between runs\n",
+        "\t    --proxy       HTTP proxy for portal requests, env for the environment's (default) or none\n",
+        "\t    --config      Configuration file (default is ~/.config/meo-wifi/config)\n",
+        "\t    --dns         Nameserver used for portal hosts (default is system)\n",
+        "\t    --host        Resolve a portal host to a fixed address, may be repeated\n",
+        "\t    --trust-store PEM file with the CA certificates trusted for portals\n",
+        "\t    --pin         Legacy portal certificate key pin, may be repeated\n",
+        "\t    --portal-domain Domain the captive portal must redirect to, may be repeated\n",
+        "\t    --allowed-oui Only log in on access points from this vendor, may be repeated\n",
+        "\t    --hotspot-check Verify the hotspot redirects to the portal domains before logging in (default when they are known)\n",
+        "\t    --no-hotspot-check Skip the hotspot check\n",
+        "\t    --max-login-failures Rejected logins before giving up on the account (default is 3)\n",
+        "\t    --reset-login-limit Forget rejected logins, e.g. after fixing the password\n",
+        "\t    --status      Show the login attempt state of the account and exit\n",
+        "\t    --daemon      Run in continuous mode, taking commands on a control socket\n",
+        "\t    --socket      Control socket path (default is $XDG_RUNTIME_DIR/meo-wifi.sock)\n",
+        "\t    --ctl         Send status, force-relogin, pause, resume, logout, switch-target <connection> or reload-config to the daemon\n",
+        "\t    --pid-file    Lock file that keeps a second instance from running (default is /run/lock/meo-wifi.pid)\n",
+        "\t    --forward     When another instance runs, ask its daemon to log in on the target instead\n",
+        "\t    --generate-systemd-unit Print a service running the daemon with the other options given\n",
+        "\t    --dispatcher  Log in on behalf of a NetworkManager dispatcher event, leaving the connection alone\n",
+        "\t    --install-dispatcher Install a NetworkManager dispatcher script running with the other options given\n",
         "\t-h, --help        Display this help message\n",
         "\t-v, --version     Display the version number"
     )
 }
 
+fn seconds(value: Option<&String>, name: &str) -> Result<Duration, String> {
+    let value = value.ok_or(format!("Missing {}", name))?;
+    value.parse().map(Duration::from_secs).map_err(|_| format!("Invalid {}: {}", name, value))
+}
+
 pub fn new() -> Result<Output, String> {
+    const DEFAULT_TARGET: &str = "MEO-WiFi";
     let args: Vec<String> = env::args().skip(1).collect();
     let mut username = None;
     let mut password = None;
     let mut connection_mode = Mode::OneShot;
+    let mut probe = Probe::Tcp;
+    let mut validation = Validation::Strict;
+    let mut portal = Portal::Legacy;
+    let mut portal_file = None;
+    let mut captive_api = CaptiveApi::Dhcp;
+    let mut max_session = None;
+    let mut relogin_margin = Duration::from_secs(60);
+    let mut timeout = None;
+    let mut user_agent = None;
+    let mut cookie_file = None;
+    let mut proxy = None;
+    let mut config = None;
+    let mut dns = None;
+    let mut hosts = Vec::new();
+    let mut trust_store = None;
+    let mut pins = Vec::new();
+    let mut portal_domains = Vec::new();
+    let mut allowed_ouis = Vec::new();
+    let mut hotspot_check = None;
+    let mut max_login_failures = 3;
+    let mut reset_login_limit = false;
+    let mut socket = None;
+    let mut control = None;
+    let mut pid_file = None;
+    let mut forward = false;
+    let mut dispatcher = None;
+    let mut target = DEFAULT_TARGET.to_string();
 
     let mut args_iter = args.iter().peekable();
     while let Some(arg) = args_iter.next() {
@@ -93,6 +370,139 @@ pub fn new() -> Result<Output, String> {
             "-c" | "--continuous" => {
                 connection_mode = Mode::Continuous;
             }
+            "--portal" => {
+                portal = match args_iter.next().map(|s| s.as_str()) {
+                    Some("legacy") => Portal::Legacy,
+                    Some("modern") => Portal::Modern,
+                    Some("auto") => Portal::Auto,
+                    Some("fon") => Portal::Fon,
+                    Some("wispr") => Portal::Wispr,
+                    Some(other) => return Err(format!("Unknown portal: {}", other)),
+                    None => return Err("Missing portal".to_string()),
+                };
+            }
+            "-t" | "--target" => {
+                target = args_iter.next().ok_or("Missing target")?.to_string();
+            }
+            "--portal-file" => {
+                portal_file = Some(args_iter.next().ok_or("Missing portal file")?.to_string());
+            }
+            "--probe" => {
+                probe = match args_iter.next().map(|s| s.as_str()) {
+                    Some("tcp") => Probe::Tcp,
+                    Some("http") => Probe::Http,
+                    Some("nm") => Probe::Nm,
+                    Some(other) => return Err(format!("Unknown probe: {}", other)),
+                    None => return Err("Missing probe".to_string()),
+                };
+            }
+            "--captive-api" => {
+                captive_api = match args_iter.next().map(|s| s.as_str()) {
+                    Some("dhcp") => CaptiveApi::Dhcp,
+                    Some("off") => CaptiveApi::Off,
+                    Some(url) if url.starts_with("https://") => CaptiveApi::Url(url.to_string()),
+                    Some(url) if url.starts_with("http://") => {
+                        return Err(format!("Captive API must be served over HTTPS: {}", url))
+                    }
+                    Some(other) => return Err(format!("Unknown captive API: {}", other)),
+                    None => return Err("Missing captive API".to_string()),
+                };
+            }
+            "--max-session" => {
+                max_session = Some(seconds(args_iter.next(), "max session")?);
+            }
+            "--relogin-margin" => {
+                relogin_margin = seconds(args_iter.next(), "relogin margin")?;
+            }
+            "--timeout" => {
+                timeout = Some(seconds(args_iter.next(), "timeout")?);
+            }
+            "--user-agent" => {
+                user_agent = Some(args_iter.next().ok_or("Missing user agent")?.to_string());
+            }
+            "--cookie-file" => {
+                cookie_file = Some(args_iter.next().ok_or("Missing cookie file")?.to_string());
+            }
+            "--proxy" => {
+                proxy = Some(args_iter.next().ok_or("Missing proxy")?.to_string());
+            }
+            "--config" => {
+                config = Some(args_iter.next().ok_or("Missing config file")?.to_string());
+            }
+            "--dns" => {
+                dns = Some(args_iter.next().ok_or("Missing DNS server")?.to_string());
+            }
+            "--host" => {
+                hosts.push(args_iter.next().ok_or("Missing host override")?.to_string());
+            }
+            "--trust-store" => {
+                trust_store = Some(args_iter.next().ok_or("Missing trust store")?.to_string());
+            }
+            "--pin" => {
+                pins.push(args_iter.next().ok_or("Missing pin")?.to_string());
+            }
+            "--portal-domain" => {
+                portal_domains.push(args_iter.next().ok_or("Missing portal domain")?.to_string());
+            }
+            "--allowed-oui" => {
+                allowed_ouis.push(args_iter.next().ok_or("Missing OUI")?.to_string());
+            }
+            "--hotspot-check" => {
+                hotspot_check = Some(true);
+            }
+            "--no-hotspot-check" => {
+                hotspot_check = Some(false);
+            }
+            "--max-login-failures" => {
+                let value = args_iter.next().ok_or("Missing max login failures")?;
+                max_login_failures = value.parse().map_err(|_| format!("Invalid max login failures: {}", value))?;
+            }
+            "--reset-login-limit" => {
+                reset_login_limit = true;
+            }
+            "--status" => {
+                connection_mode = Mode::Status;
+            }
+            "--daemon" => {
+                connection_mode = Mode::Daemon;
+            }
+            "--generate-systemd-unit" => {
+                connection_mode = Mode::SystemdUnit;
+            }
+            "--dispatcher" => {
+                let interface = args_iter.next().ok_or("Missing dispatcher interface")?.to_string();
+                let action = args_iter.next().ok_or("Missing dispatcher action")?.to_string();
+                dispatcher = Some((interface, action));
+                connection_mode = Mode::Dispatcher;
+            }
+            "--install-dispatcher" => {
+                connection_mode = Mode::InstallDispatcher;
+            }
+            "--socket" => {
+                socket = Some(args_iter.next().ok_or("Missing socket path")?.to_string());
+            }
+            "--pid-file" => {
+                pid_file = Some(args_iter.next().ok_or("Missing PID file")?.to_string());
+            }
+            "--forward" => {
+                forward = true;
+            }
+            "--ctl" => {
+                let command = args_iter.next().ok_or("Missing control command")?;
+                control = Some(match command.as_str() {
+                    "switch-target" => format!("{} {}", command, args_iter.next().ok_or("Missing target connection")?),
+                    _ => command.to_string()
+                });
+            }
+            "--validation" => {
+                validation = match args_iter.next().map(|s| s.as_str()) {
+                    Some("strict") => Validation::Strict,
+                    Some("warn") => Validation::Warn,
+                    Some("off") => Validation::Off,
+                    Some(other) => return Err(format!("Unknown validation: {}", other)),
+                    None => return Err("Missing validation".to_string()),
+                };
+            }
             "-h" | "--help" => {
                 return Ok(Output::Info(usage_instructions()));
             }
@@ -103,16 +513,46 @@ pub fn new() -> Result<Output, String> {
         }
     }
 
-    match (username, password) {
-        (Some(u), Some(p)) => {
-            Ok(Output::Args(Args { 
-                username: u, 
-                password: p, 
-                mode: connection_mode 
-            }))
-        },
+    if max_session.is_some_and(|max_session| relogin_margin >= max_session) {
+        return Err("Relogin margin must be shorter than the max session".to_string());
+    }
+
+    match (&username, &password) {
         (None, Some(_)) => Err("Missing username".to_string()),
         (Some(_), None) => Err("Missing password".to_string()),
-        (None, None) => Err("Missing username and password".to_string()),
+        _ => {
+            Ok(Output::Args(Box::new(Args {
+                username,
+                password,
+                mode: connection_mode,
+                probe,
+                validation,
+                portal,
+                portal_file,
+                captive_api,
+                max_session,
+                relogin_margin,
+                timeout,
+                user_agent,
+                cookie_file,
+                proxy,
+                config,
+                dns,
+                hosts,
+                trust_store,
+                pins,
+                portal_domains,
+                allowed_ouis,
+                hotspot_check,
+                max_login_failures,
+                reset_login_limit,
+                socket,
+                control,
+                pid_file,
+                forward,
+                dispatcher,
+                target
+            })))
+        }
     }
 }
\ No newline at end of file
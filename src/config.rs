@@ -0,0 +1,191 @@
+use std::path::{Path, PathBuf};
+use crate::error::Source;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    Io(PathBuf, Source),
+    Syntax(usize, String),
+    UnknownKey(usize, String)
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(_, e) => Some(e.inner()),
+            _ => None
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(path, e) => write!(f, "Failed to read config file {}: {}", path.display(), e),
+            Error::Syntax(line, text) => write!(f, "Config line {} is not `key = value`: {}", line, text),
+            Error::UnknownKey(line, key) => write!(f, "Unknown config key on line {}: {}", line, key),
+        }
+    }
+}
+
+/// Settings read from the configuration file. Each line holds a
+/// `key = value` pair; blank lines and lines starting with `#` are skipped.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    proxy: Option<String>,
+    no_proxy: Option<String>,
+    dns: Option<String>,
+    hosts: Option<String>,
+    trust_store: Option<String>,
+    pins: Option<String>,
+    portal_domains: Option<String>,
+    allowed_ouis: Option<String>,
+    accounts: Vec<(String, String)>
+}
+
+impl Config {
+
+    /// `$XDG_CONFIG_HOME/meo-wifi/config`, or `~/.config/meo-wifi/config`.
+    pub fn default_path() -> Option<PathBuf> {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .map(|dir| dir.join("meo-wifi").join("config"))
+    }
+
+    /// `$XDG_STATE_HOME/meo-wifi`, or `~/.local/state/meo-wifi`, where
+    /// state that must survive restarts is kept.
+    pub fn state_dir() -> Option<PathBuf> {
+        std::env::var_os("XDG_STATE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("state")))
+            .map(|dir| dir.join("meo-wifi"))
+    }
+
+    /// `$XDG_RUNTIME_DIR`, where the control socket and the lock file live.
+    pub fn runtime_dir() -> Option<PathBuf> {
+        std::env::var_os("XDG_RUNTIME_DIR")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+    }
+
+    /// Reads the file at `path`. A missing file is only an error when
+    /// `required` is set, otherwise it yields the defaults.
+    pub fn load(path: &Path, required: bool) -> Result<Self, Error> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text),
+            Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(Error::Io(path.to_path_buf(), Source::new(e)))
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut config = Self::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| Error::Syntax(index + 1, line.to_string()))?;
+            let value = value.trim().trim_matches('"').to_string();
+            match key.trim() {
+                "proxy" => config.proxy = Some(value),
+                "no_proxy" => config.no_proxy = Some(value),
+                "dns" => config.dns = Some(value),
+                "hosts" => config.hosts = Some(value),
+                "trust_store" => config.trust_store = Some(value),
+                "pins" => config.pins = Some(value),
+                "portal_domains" => config.portal_domains = Some(value),
+                "allowed_ouis" => config.allowed_ouis = Some(value),
+                "account" => {
+                    let (username, password) = value
+                        .split_once(':')
+                        .ok_or_else(|| Error::Syntax(index + 1, "account = <username>:<password>".to_string()))?;
+                    config.accounts.push((username.trim().to_string(), password.to_string()));
+                }
+                key => return Err(Error::UnknownKey(index + 1, key.to_string()))
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn proxy(&self) -> Option<&str> {
+        self.proxy.as_deref()
+    }
+
+    pub fn no_proxy(&self) -> Option<&str> {
+        self.no_proxy.as_deref()
+    }
+
+    pub fn dns(&self) -> Option<&str> {
+        self.dns.as_deref()
+    }
+
+    pub fn hosts(&self) -> Option<&str> {
+        self.hosts.as_deref()
+    }
+
+    pub fn trust_store(&self) -> Option<&str> {
+        self.trust_store.as_deref()
+    }
+
+    pub fn pins(&self) -> Option<&str> {
+        self.pins.as_deref()
+    }
+
+    pub fn portal_domains(&self) -> Option<&str> {
+        self.portal_domains.as_deref()
+    }
+
+    pub fn allowed_ouis(&self) -> Option<&str> {
+        self.allowed_ouis.as_deref()
+    }
+
+    /// Credentials listed with `account = <username>:<password>`, in order.
+    pub fn accounts(&self) -> &[(String, String)] {
+        &self.accounts
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_keys_skipping_comments() {
+        let config = Config::parse("# portal traffic\nproxy = http://proxy.lan:3128\n\nno_proxy = \"meo.pt,fon.com\"\nhosts = a.pt=1.2.3.4\n").unwrap();
+
+        assert_eq!(config.proxy(), Some("http://proxy.lan:3128"));
+        assert_eq!(config.no_proxy(), Some("meo.pt,fon.com"));
+        assert_eq!(config.hosts(), Some("a.pt=1.2.3.4"));
+    }
+
+    #[test]
+    fn collects_accounts_in_order() {
+        let config = Config::parse("account = a@meo.pt:pass:word
+account = 912345678:Secret1").unwrap();
+
+        assert_eq!(config.accounts(), &[
+            ("a@meo.pt".to_string(), "pass:word".to_string()),
+            ("912345678".to_string(), "Secret1".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn reports_line_of_bad_entries() {
+        assert_eq!(Config::parse("proxy = x\nproxy"), Err(Error::Syntax(2, "proxy".to_string())));
+        assert_eq!(Config::parse("\nprox = x"), Err(Error::UnknownKey(2, "prox".to_string())));
+    }
+
+    #[test]
+    fn missing_file_is_only_fatal_when_required() {
+        let path = Path::new("/nonexistent/meo-wifi/config");
+
+        assert_eq!(Config::load(path, false), Ok(Config::default()));
+        assert!(matches!(Config::load(path, true), Err(Error::Io(..))));
+    }
+}
@@ -0,0 +1,51 @@
+/// Connectivity state NetworkManager found with its own checks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connectivity {
+    /// No network connection at all (`none`).
+    Offline,
+    /// Connected, but traffic is held by a captive portal.
+    Portal,
+    /// Connected, but the connectivity check failed.
+    Limited,
+    Full
+}
+
+impl std::str::FromStr for Connectivity {
+    type Err = String;
+
+    /// Parses `nmcli` output as well as `$CONNECTIVITY_STATE` from the
+    /// dispatcher, which is in upper case.
+    fn from_str(state: &str) -> Result<Self, Self::Err> {
+        match state.trim().to_lowercase().as_str() {
+            "none" => Ok(Self::Offline),
+            "portal" => Ok(Self::Portal),
+            "limited" => Ok(Self::Limited),
+            "full" => Ok(Self::Full),
+            _ => Err(format!("Unknown connectivity state: {}", state.trim()))
+        }
+    }
+}
+
+impl std::fmt::Display for Connectivity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Offline => write!(f, "none"),
+            Self::Portal => write!(f, "portal"),
+            Self::Limited => write!(f, "limited"),
+            Self::Full => write!(f, "full"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_nmcli_and_dispatcher_states() {
+        assert_eq!("portal\n".parse(), Ok(Connectivity::Portal));
+        assert_eq!("LIMITED".parse(), Ok(Connectivity::Limited));
+        assert_eq!("none".parse::<Connectivity>().map(|state| state.to_string()), Ok("none".to_string()));
+        assert!("unknown".parse::<Connectivity>().is_err());
+    }
+}
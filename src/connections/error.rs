@@ -1,27 +1,54 @@
+use crate::error::{Kind, Source};
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
     AlreadyActive,
     Unavailable,
     NoInternet,
-    Custom(String)
+    BackendMissing(String),
+    Command(String),
+    Io(Source)
+}
+
+impl Error {
+
+    pub fn kind(&self) -> Kind {
+        match self {
+            Self::AlreadyActive => Kind::Internal,
+            Self::Unavailable => Kind::Network,
+            Self::NoInternet => Kind::Network,
+            Self::BackendMissing(_) => Kind::BackendMissing,
+            Self::Command(_) => Kind::Network,
+            Self::Io(_) => Kind::Internal
+        }
+    }
+
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let msg = match self {
-            Self::AlreadyActive => "Connection is already active",
-            Self::Unavailable => "Connection is unavailable",
-            Self::NoInternet => "No internet connection",
-            Self::Custom(msg) => msg
-        };
-        write!(f, "{}", msg)
+        match self {
+            Self::AlreadyActive => write!(f, "Connection is already active"),
+            Self::Unavailable => write!(f, "Connection is unavailable"),
+            Self::NoInternet => write!(f, "No internet connection"),
+            Self::BackendMissing(program) => write!(f, "{} is not installed", program),
+            Self::Command(msg) => write!(f, "{}", msg),
+            Self::Io(e) => write!(f, "{}", e)
+        }
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e.inner()),
+            _ => None
+        }
+    }
+}
 
 impl From<std::io::Error> for Error {
     fn from(e: std::io::Error) -> Self {
-        Self::Custom(e.to_string())
+        Self::Io(Source::new(e))
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,176 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use super::error::Error;
+use super::connectivity::Connectivity;
+use crate::testing::{Event, Journal};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Operation {
+    Connect,
+    Disconnect,
+}
+
+#[derive(Debug, Clone)]
+struct Profile {
+    name: String,
+    active: bool
+}
+
+#[derive(Debug, Default)]
+struct State {
+    profiles: Vec<Profile>,
+    captive_portal_api: Option<String>,
+    bssid: Option<String>,
+    connectivity: Option<Connectivity>,
+    failures: HashMap<(Operation, String), VecDeque<Error>>
+}
+
+/// In-memory stand-in for `Nmcli`, sharing its state between clones.
+#[derive(Debug, Clone)]
+pub struct Fake {
+    state: Arc<Mutex<State>>,
+    journal: Journal
+}
+
+impl Fake {
+
+    pub fn new(journal: &Journal) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State::default())),
+            journal: journal.clone()
+        }
+    }
+
+    pub fn with_profile(self, name: &str, active: bool) -> Self {
+        self.state.lock().unwrap().profiles.push(Profile {
+            name: name.to_string(),
+            active
+        });
+        self
+    }
+
+    pub fn fail_connect(&self, name: &str, error: Error) {
+        self.inject(Operation::Connect, name, error)
+    }
+
+    pub fn fail_disconnect(&self, name: &str, error: Error) {
+        self.inject(Operation::Disconnect, name, error)
+    }
+
+    /// Flips a profile's state without going through the journal, as if
+    /// the network dropped or came back on its own.
+    pub fn set_active(&self, name: &str, active: bool) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(profile) = Self::find(&mut state, name) {
+            profile.active = active;
+        }
+    }
+
+    pub fn set_captive_portal_api(&self, url: Option<String>) {
+        self.state.lock().unwrap().captive_portal_api = url;
+    }
+
+    pub fn set_bssid(&self, bssid: Option<&str>) {
+        self.state.lock().unwrap().bssid = bssid.map(str::to_string);
+    }
+
+    pub fn set_connectivity(&self, connectivity: Option<Connectivity>) {
+        self.state.lock().unwrap().connectivity = connectivity;
+    }
+
+    fn inject(&self, operation: Operation, name: &str, error: Error) {
+        self.state
+            .lock()
+            .unwrap()
+            .failures
+            .entry((operation, name.to_lowercase()))
+            .or_default()
+            .push_back(error);
+    }
+
+    fn injected(state: &mut State, operation: Operation, name: &str) -> Option<Error> {
+        state
+            .failures
+            .get_mut(&(operation, name.to_lowercase()))
+            .and_then(|queue| queue.pop_front())
+    }
+
+    fn find<'s>(state: &'s mut State, name: &str) -> Option<&'s mut Profile> {
+        let name = name.to_lowercase();
+        state.profiles.iter_mut().find(|p| p.name.to_lowercase() == name)
+    }
+
+}
+
+impl super::Trait for Fake {
+
+    fn active(&self) -> Option<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .profiles
+            .iter()
+            .find(|p| p.active)
+            .map(|p| p.name.clone())
+    }
+
+    fn is_connected(&self, name: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        Self::find(&mut state, name).is_some_and(|p| p.active)
+    }
+
+    fn captive_portal_api(&self, connection: &str) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        let active = Self::find(&mut state, connection).is_some_and(|p| p.active);
+        state.captive_portal_api.clone().filter(|_| active)
+    }
+
+    fn bssid(&self, connection: &str) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        let active = Self::find(&mut state, connection).is_some_and(|p| p.active);
+        state.bssid.clone().filter(|_| active)
+    }
+
+    fn connectivity(&self) -> Option<Connectivity> {
+        self.state.lock().unwrap().connectivity
+    }
+
+    fn connect(&self, connection: &str) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(error) = Self::injected(&mut state, Operation::Connect, connection) {
+            return Err(error);
+        }
+
+        let target = Self::find(&mut state, connection).ok_or(Error::Unavailable)?;
+        if target.active {
+            return Err(Error::AlreadyActive);
+        }
+        let target = target.name.clone();
+
+        for profile in state.profiles.iter_mut().filter(|p| p.active) {
+            profile.active = false;
+            self.journal.record(Event::Disconnect(profile.name.clone()));
+        }
+        if let Some(profile) = Self::find(&mut state, &target) {
+            profile.active = true;
+        }
+        self.journal.record(Event::Connect(target));
+        Ok(())
+    }
+
+    fn disconnect(&self, connection: &str) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(error) = Self::injected(&mut state, Operation::Disconnect, connection) {
+            return Err(error);
+        }
+
+        let profile = Self::find(&mut state, connection).ok_or(Error::Unavailable)?;
+        if !profile.active {
+            return Err(Error::Command(format!("{} is not active", profile.name)));
+        }
+        profile.active = false;
+        self.journal.record(Event::Disconnect(profile.name.clone()));
+        Ok(())
+    }
+
+}
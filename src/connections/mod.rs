@@ -1,8 +1,14 @@
 mod nmcli;
 mod error;
+mod connectivity;
+#[cfg(test)]
+mod fake;
 
 pub (super) use error::Error;
 pub (super) use nmcli::Nmcli;
+pub (super) use connectivity::Connectivity;
+#[cfg(test)]
+pub (super) use fake::Fake;
 
 pub (super) trait Trait: Clone {
 
@@ -10,6 +16,21 @@ pub (super) trait Trait: Clone {
     fn connect(&self, connection: &str) -> Result<(), error::Error>;
     fn disconnect(&self, connection: &str) -> Result<(), error::Error>;
     fn is_connected(&self, name: &str) -> bool;
+    /// Captive Portal API URI the connection received through DHCP option
+    /// 114 (RFC 8910), if any.
+    fn captive_portal_api(&self, _connection: &str) -> Option<String> {
+        None
+    }
+    /// BSSID of the access point the connection is associated with, for
+    /// wireless connections.
+    fn bssid(&self, _connection: &str) -> Option<String> {
+        None
+    }
+    /// Connectivity state from NetworkManager's own checks, if it runs
+    /// them and the state is known.
+    fn connectivity(&self) -> Option<Connectivity> {
+        None
+    }
     fn reconnect(&self) -> Result<(), error::Error> {
         if let Some(active) = self.active() {
             self.disconnect(&active)?;
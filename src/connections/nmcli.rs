@@ -1,6 +1,7 @@
 use std::process::Command;
-use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::io::ErrorKind as IoErrorKind;
 use super::error::Error;
+use super::connectivity::Connectivity;
 use crate::log;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -27,19 +28,49 @@ impl Nmcli {
         Self {}
     }
 
-    fn execute(args: &[&str]) -> Result<String, IoError> {
+    fn execute(args: &[&str]) -> Result<String, Error> {
         let output = Command::new("nmcli")
             .args(args)
-            .output()?;
+            .output()
+            .map_err(|e| match e.kind() {
+                IoErrorKind::NotFound => Error::BackendMissing("nmcli".to_string()),
+                _ => Error::from(e)
+            })?;
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(IoError::new(IoErrorKind::Other, format!("nmcli command failed: {}", stderr)));
+            return Err(Error::Command(format!("nmcli command failed: {}", stderr.trim())));
         }
 
-        String::from_utf8(output.stdout)
-            .map(|s| s.trim().to_string())
-            .map_err(|e| IoError::new(IoErrorKind::InvalidData, e))
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// Undoes the escaping of `:` and `\` in terse (`-t`) output values.
+    fn unescape(value: &str) -> String {
+        let mut unescaped = String::with_capacity(value.len());
+        let mut chars = value.chars();
+        while let Some(c) = chars.next() {
+            match (c, chars.clone().next()) {
+                ('\\', Some(next @ (':' | '\\'))) => {
+                    unescaped.push(next);
+                    chars.next();
+                }
+                _ => unescaped.push(c)
+            }
+        }
+        unescaped
+    }
+
+    /// Captive Portal API URI among the `DHCP4.OPTION[n]:name = value`
+    /// lines of `nmcli -t -f DHCP4 connection show`.
+    fn parse_captive_portal_api(output: &str) -> Option<String> {
+        const OPTION_NAMES: [&str; 3] = ["captive_portal", "unknown_114", "private_114"];
+        output
+            .lines()
+            .filter_map(|line| line.split_once(':').map(|(_, option)| option))
+            .filter_map(|option| option.split_once('='))
+            .find(|(name, _)| OPTION_NAMES.contains(&name.trim()))
+            .map(|(_, value)| Self::unescape(value.trim()))
     }
 
     fn parse_network_type(type_str: &str) -> Type {
@@ -54,7 +85,7 @@ impl Nmcli {
         }
     }
 
-    fn connections(&self) -> Result<Vec<Connection>, IoError> {
+    fn connections(&self) -> Result<Vec<Connection>, Error> {
         let output = Self::execute(&["-t", "-f", "NAME,TYPE,ACTIVE,DEVICE", "connection", "show"])?;
         let connections: Vec<_> = output.lines().filter_map(|line| {
             let parts: Vec<&str> = line.split(':').collect();
@@ -105,6 +136,25 @@ impl super::Trait for Nmcli {
             .unwrap_or(false)
     }
 
+    fn captive_portal_api(&self, connection: &str) -> Option<String> {
+        let output = Self::execute(&["-t", "-f", "DHCP4", "connection", "show", connection]).ok()?;
+        Self::parse_captive_portal_api(&output)
+    }
+
+    fn bssid(&self, connection: &str) -> Option<String> {
+        let device = Self::execute(&["-g", "GENERAL.DEVICES", "connection", "show", connection]).ok()?;
+        let device = device.lines().next()?.trim();
+        let output = Self::execute(&["-t", "-f", "IN-USE,BSSID", "device", "wifi", "list", "ifname", device, "--rescan", "no"]).ok()?;
+        output
+            .lines()
+            .find_map(|line| line.strip_prefix("*:"))
+            .map(|bssid| Self::unescape(bssid).to_uppercase())
+    }
+
+    fn connectivity(&self) -> Option<Connectivity> {
+        Self::execute(&["networking", "connectivity"]).ok()?.parse().ok()
+    }
+
     fn connect(&self, connection: &str) -> Result<(), Error> {
         let connections = self.connections()?;
         let connection_name = connection.to_lowercase();
@@ -136,9 +186,25 @@ impl super::Trait for Nmcli {
             .iter()
             .find(|conn| conn.name.to_lowercase().eq(&connection_name))
             .ok_or(Error::Unavailable)?;
-        Self::execute(&["connection", "down", &connection.name])
-            .map(|_| ())
-            .map_err(|e| Error::from(e))
+        Self::execute(&["connection", "down", &connection.name]).map(|_| ())
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unescapes_captive_portal_api_from_terse_output() {
+        let output = "DHCP4.OPTION[1]:broadcast_address = 10.0.0.255\n\
+                      DHCP4.OPTION[2]:captive_portal = https\\://portal.example\\:8443/capport?a=b\\\\c\n\
+                      DHCP4.OPTION[3]:dhcp_lease_time = 3600";
+
+        assert_eq!(
+            Nmcli::parse_captive_portal_api(output).as_deref(),
+            Some("https://portal.example:8443/capport?a=b\\c")
+        );
+        assert_eq!(Nmcli::parse_captive_portal_api("DHCP4.OPTION[1]:routers = 10.0.0.1"), None);
+    }
+}
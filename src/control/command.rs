@@ -0,0 +1,66 @@
+/// Commands a running daemon accepts on its control socket, one per line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Status,
+    ForceRelogin,
+    Pause,
+    Resume,
+    Logout,
+    SwitchTarget(String),
+    ReloadConfig
+}
+
+impl std::str::FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let (name, argument) = match line.trim().split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, Some(argument.trim())),
+            None => (line.trim(), None)
+        };
+        match (name, argument) {
+            ("status", None) => Ok(Self::Status),
+            ("force-relogin", None) => Ok(Self::ForceRelogin),
+            ("pause", None) => Ok(Self::Pause),
+            ("resume", None) => Ok(Self::Resume),
+            ("logout", None) => Ok(Self::Logout),
+            ("switch-target", Some(target)) if !target.is_empty() => Ok(Self::SwitchTarget(target.to_string())),
+            ("switch-target", _) => Err("Missing target connection".to_string()),
+            ("reload-config", None) => Ok(Self::ReloadConfig),
+            _ => Err(format!("Unknown control command: {}", line.trim()))
+        }
+    }
+}
+
+impl std::fmt::Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Status => write!(f, "status"),
+            Self::ForceRelogin => write!(f, "force-relogin"),
+            Self::Pause => write!(f, "pause"),
+            Self::Resume => write!(f, "resume"),
+            Self::Logout => write!(f, "logout"),
+            Self::SwitchTarget(target) => write!(f, "switch-target {}", target),
+            Self::ReloadConfig => write!(f, "reload-config"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_what_it_displays() {
+        for command in [Command::Status, Command::ReloadConfig, Command::SwitchTarget("FON Home".to_string())] {
+            assert_eq!(command.to_string().parse(), Ok(command));
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_commands_and_missing_arguments() {
+        assert_eq!("restart".parse::<Command>(), Err("Unknown control command: restart".to_string()));
+        assert_eq!("status now".parse::<Command>(), Err("Unknown control command: status now".to_string()));
+        assert_eq!("switch-target".parse::<Command>(), Err("Missing target connection".to_string()));
+    }
+}
@@ -0,0 +1,134 @@
+mod command;
+mod socket;
+
+pub (super) use command::Command;
+pub (super) use socket::{default_socket, request, serve};
+
+use std::collections::VecDeque;
+use crate::log;
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// A command waiting to be handled, along with where to send the reply.
+#[derive(Debug)]
+pub struct Request {
+    pub command: Command,
+    reply: mpsc::Sender<String>,
+    expires: Option<Instant>
+}
+
+impl Request {
+    pub fn reply(self, text: impl Into<String>) {
+        let _ = self.reply.send(text.into());
+    }
+}
+
+/// Where the loop steering the session left off, handed over to the one
+/// taking its place after a configuration reload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Handover {
+    pub target: String,
+    pub original: Option<String>,
+    pub paused: bool
+}
+
+/// Queue between the control socket and the loop that steers the session,
+/// sharing its state between clones.
+#[derive(Debug, Clone, Default)]
+pub struct Control {
+    queue: Arc<(Mutex<VecDeque<Request>>, Condvar)>,
+    handover: Arc<Mutex<Option<Handover>>>
+}
+
+impl Control {
+
+    /// Queues `command` and returns where its reply will arrive.
+    #[allow(dead_code)]
+    pub fn send(&self, command: Command) -> mpsc::Receiver<String> {
+        self.queue(command, None)
+    }
+
+    /// Queues `command` like `send`, but drops it unhandled once `timeout`
+    /// passed, as whoever sent it stopped waiting for the reply by then.
+    pub fn send_within(&self, command: Command, timeout: Duration) -> mpsc::Receiver<String> {
+        self.queue(command, Some(Instant::now() + timeout))
+    }
+
+    fn queue(&self, command: Command, expires: Option<Instant>) -> mpsc::Receiver<String> {
+        let (reply, receiver) = mpsc::channel();
+        let (queue, ready) = &*self.queue;
+        queue.lock().unwrap().push_back(Request { command, reply, expires });
+        ready.notify_all();
+        receiver
+    }
+
+    pub fn has_pending(&self) -> bool {
+        !self.queue.0.lock().unwrap().is_empty()
+    }
+
+    /// Next command to handle, skipping the ones that expired.
+    pub fn take(&self) -> Option<Request> {
+        let mut queue = self.queue.0.lock().unwrap();
+        while let Some(request) = queue.pop_front() {
+            match request.expires {
+                Some(expires) if expires <= Instant::now() => {
+                    log::warn(&format!("Dropping control command {}, nobody waits for its reply anymore", request.command));
+                }
+                _ => return Some(request)
+            }
+        }
+        None
+    }
+
+    pub fn hand_over(&self, handover: Handover) {
+        *self.handover.lock().unwrap() = Some(handover);
+    }
+
+    pub fn take_handover(&self) -> Option<Handover> {
+        self.handover.lock().unwrap().take()
+    }
+
+    /// Blocks for up to `timeout`, returning early once a command is queued.
+    pub fn wait(&self, timeout: Duration) {
+        let (queue, ready) = &*self.queue;
+        let queue = queue.lock().unwrap();
+        let _ = ready.wait_timeout_while(queue, timeout, |queue| queue.is_empty());
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn wakes_waiter_when_command_arrives() {
+        let control = Control::default();
+        let sender = control.clone();
+        let started = Instant::now();
+        let replies = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            sender.send(Command::Status)
+        });
+
+        control.wait(Duration::from_secs(5));
+        let request = control.take().unwrap();
+        assert_eq!(request.command, Command::Status);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        request.reply("paused");
+        assert_eq!(replies.join().unwrap().recv().unwrap(), "paused");
+    }
+
+    #[test]
+    fn drops_commands_nobody_waits_for() {
+        let control = Control::default();
+        let abandoned = control.send_within(Command::Logout, Duration::ZERO);
+        control.send_within(Command::Status, Duration::from_secs(30));
+
+        assert_eq!(control.take().unwrap().command, Command::Status);
+        assert!(control.take().is_none());
+        assert!(abandoned.recv().is_err());
+    }
+}
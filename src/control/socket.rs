@@ -0,0 +1,128 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use crate::error::Source;
+use crate::log;
+use super::{Command, Control};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    AlreadyRunning(PathBuf),
+    NotRunning(PathBuf),
+    Io(PathBuf, Source)
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(_, e) => Some(e.inner()),
+            _ => None
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::AlreadyRunning(path) => write!(f, "Another instance is already listening on {}", path.display()),
+            Error::NotRunning(path) => write!(f, "No running instance listens on {}", path.display()),
+            Error::Io(path, e) => write!(f, "Control socket {} failed: {}", path.display(), e),
+        }
+    }
+}
+
+/// `meo-wifi.sock` in the runtime directory, when there is one. There is
+/// no fallback to a shared directory, where another user could take the
+/// name first.
+pub fn default_socket() -> Option<PathBuf> {
+    crate::config::Config::runtime_dir().map(|dir| dir.join("meo-wifi.sock"))
+}
+
+/// Listens on `path` in a background thread, queueing every command on
+/// `control` and writing back its reply. Fails when another instance
+/// already answers on `path`; a stale socket file is replaced.
+pub fn serve(path: &Path, control: Control) -> Result<(), Error> {
+    let io = |e: std::io::Error| Error::Io(path.to_path_buf(), Source::new(e));
+    if UnixStream::connect(path).is_ok() {
+        return Err(Error::AlreadyRunning(path.to_path_buf()));
+    }
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(io(e)),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path).map_err(io)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).map_err(io)?;
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| answer(stream, &control));
+            if let Err(error) = result {
+                log::warn(&format!("Control connection failed: {}", error));
+            }
+        }
+    });
+    Ok(())
+}
+
+fn answer(mut stream: UnixStream, control: &Control) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let reply = match line.parse::<Command>() {
+        Ok(command) => control
+            .send_within(command, TIMEOUT)
+            .recv_timeout(TIMEOUT)
+            .unwrap_or_else(|_| "error: no reply from the daemon".to_string()),
+        Err(error) => format!("error: {}", error)
+    };
+    writeln!(stream, "{}", reply)
+}
+
+/// Sends `command` to the instance listening on `path` and returns its reply.
+pub fn request(path: &Path, command: &Command) -> Result<String, Error> {
+    let io = |e: std::io::Error| Error::Io(path.to_path_buf(), Source::new(e));
+    let mut stream = UnixStream::connect(path).map_err(|_| Error::NotRunning(path.to_path_buf()))?;
+    stream.set_read_timeout(Some(TIMEOUT)).map_err(io)?;
+    writeln!(stream, "{}", command).map_err(io)?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).map_err(io)?;
+    Ok(reply.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn socket(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("meo-wifi-{}-{}.sock", name, std::process::id()))
+    }
+
+    #[test]
+    fn relays_commands_and_replies() {
+        let path = socket("relay");
+        let control = Control::default();
+        serve(&path, control.clone()).unwrap();
+        std::thread::spawn(move || loop {
+            control.wait(TIMEOUT);
+            while let Some(request) = control.take() {
+                let reply = format!("handled {}", request.command);
+                request.reply(reply);
+            }
+        });
+
+        assert_eq!(request(&path, &Command::Pause), Ok("handled pause".to_string()));
+        assert_eq!(serve(&path, Control::default()), Err(Error::AlreadyRunning(path.clone())));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn reports_missing_instance() {
+        let path = socket("missing");
+
+        assert_eq!(request(&path, &Command::Status), Err(Error::NotRunning(path)));
+    }
+}
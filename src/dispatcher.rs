@@ -0,0 +1,171 @@
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use crate::connections::{Connectivity, Trait as Connections};
+
+/// Where NetworkManager looks for dispatcher scripts.
+pub const SCRIPT_PATH: &str = "/etc/NetworkManager/dispatcher.d/90-meo-wifi";
+
+/// Configuration file the script reads unless given another one.
+pub const CONFIG_PATH: &str = "/etc/meo-wifi/config";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Up,
+    ConnectivityChange,
+    Other(String)
+}
+
+/// A NetworkManager dispatcher invocation: the interface and action it
+/// passes as arguments and the details it sets in the environment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    interface: String,
+    action: Action,
+    connection: Option<String>,
+    address: Option<String>,
+    connectivity: Option<Connectivity>
+}
+
+impl Event {
+
+    pub fn from_env(interface: &str, action: &str) -> Self {
+        Self::new(interface, action, |name| std::env::var(name).ok())
+    }
+
+    fn new(interface: &str, action: &str, env: impl Fn(&str) -> Option<String>) -> Self {
+        let action = match action {
+            "up" => Action::Up,
+            "connectivity-change" => Action::ConnectivityChange,
+            other => Action::Other(other.to_string())
+        };
+        Self {
+            interface: interface.to_string(),
+            action,
+            connection: env("CONNECTION_ID"),
+            // `IP4_ADDRESS_0` reads `address/prefix gateway`.
+            address: env("IP4_ADDRESS_0").and_then(|address| address.split('/').next().map(str::to_string)),
+            connectivity: env("CONNECTIVITY_STATE").and_then(|state| state.parse().ok())
+        }
+    }
+
+    /// Whether the event calls for a portal login on `target`: it came up
+    /// with an IPv4 address, or NetworkManager found connectivity to be
+    /// limited while it is active.
+    pub fn concerns(&self, target: &str, connections: &impl Connections) -> bool {
+        match self.action {
+            Action::Up => self.connection.as_deref() == Some(target) && self.address.is_some(),
+            Action::ConnectivityChange => {
+                matches!(self.connectivity, Some(Connectivity::Portal | Connectivity::Limited)) && connections.is_connected(target)
+            }
+            Action::Other(_) => false
+        }
+    }
+
+}
+
+impl std::fmt::Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.action {
+            Action::Up => write!(f, "{} up", self.connection.as_deref().unwrap_or(&self.interface))?,
+            Action::ConnectivityChange => match self.connectivity {
+                Some(connectivity) => write!(f, "connectivity {}", connectivity)?,
+                None => write!(f, "connectivity unknown")?,
+            },
+            Action::Other(action) => write!(f, "{} {}", self.interface, action)?,
+        }
+        match &self.address {
+            Some(address) => write!(f, " ({})", address),
+            None => Ok(())
+        }
+    }
+}
+
+/// Dispatcher script running `exe` with `args`, the command line it was
+/// installed from, for every event. Credentials are left out of the script
+/// and settings read from `/etc/meo-wifi`, as NetworkManager runs hooks as
+/// root with an empty environment. Like every instance, it shares the lock
+/// file of the systemd service, so events are ignored while its daemon runs.
+/// The login runs in the background, as NetworkManager waits for each
+/// script in turn and kills the ones that take too long.
+pub fn script(exe: &Path, args: &[String]) -> String {
+    let quote = |arg: &str| format!("'{}'", arg.replace('\'', "'\\''"));
+    let args = crate::args::forwarded(args).iter().map(|arg| format!(" {}", quote(arg))).collect::<String>();
+    format!(
+        "#!/bin/sh\n\
+         # Logs in to the hotspot portal when NetworkManager brings the connection up.\n\
+         # Written by meo-wifi --install-dispatcher.\n\
+         export XDG_CONFIG_HOME=/etc XDG_STATE_HOME=/var/lib XDG_RUNTIME_DIR=/run\n\
+         {}{} --dispatcher \"$1\" \"$2\" </dev/null &\n",
+        quote(&exe.to_string_lossy()),
+        args
+    )
+}
+
+/// Writes the dispatcher script to `path`, executable and only writable
+/// by its owner, as NetworkManager requires.
+pub fn install(path: &Path, script: &str) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o755)
+        .open(path)?;
+    file.write_all(script.as_bytes())?;
+    std::fs::set_permissions(path, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connections::Fake as FakeConnections;
+    use crate::testing::Journal;
+    use std::collections::HashMap;
+
+    const TARGET: &str = "MEO-WiFi";
+
+    fn event(action: &str, env: &[(&str, &str)]) -> Event {
+        let env = env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<HashMap<_, _>>();
+        Event::new("wlan0", action, |name| env.get(name).cloned())
+    }
+
+    #[test]
+    fn logs_in_when_target_comes_up_with_an_address() {
+        let connections = FakeConnections::new(&Journal::new());
+        let up = event("up", &[("CONNECTION_ID", TARGET), ("IP4_ADDRESS_0", "10.0.0.7/22 10.0.0.1")]);
+
+        assert!(up.concerns(TARGET, &connections));
+        assert_eq!(up.to_string(), "MEO-WiFi up (10.0.0.7)");
+        assert!(!event("up", &[("CONNECTION_ID", TARGET)]).concerns(TARGET, &connections));
+        assert!(!event("up", &[("CONNECTION_ID", "Home"), ("IP4_ADDRESS_0", "10.0.0.7/22")]).concerns(TARGET, &connections));
+        assert!(!event("down", &[("CONNECTION_ID", TARGET)]).concerns(TARGET, &connections));
+    }
+
+    #[test]
+    fn logs_in_on_limited_connectivity_only_while_target_is_active() {
+        let connections = FakeConnections::new(&Journal::new()).with_profile(TARGET, true);
+        let portal = event("connectivity-change", &[("CONNECTIVITY_STATE", "PORTAL")]);
+
+        assert!(portal.concerns(TARGET, &connections));
+        assert!(!event("connectivity-change", &[("CONNECTIVITY_STATE", "FULL")]).concerns(TARGET, &connections));
+        connections.set_active(TARGET, false);
+        assert!(!portal.concerns(TARGET, &connections));
+    }
+
+    #[test]
+    fn installs_executable_script() {
+        let path = std::env::temp_dir().join(format!("meo-wifi-dispatcher-{}", std::process::id()));
+        let args = ["-u", "user@meo.pt", "-p", "secret", "-t", "MEO WiFi", "--install-dispatcher"].map(String::from);
+        let script = script(Path::new("/usr/bin/meo-wifi"), &args);
+
+        install(&path, &script).unwrap();
+
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o755);
+        assert!(std::fs::read_to_string(&path).unwrap().contains(
+            "\n'/usr/bin/meo-wifi' '-t' 'MEO WiFi' --dispatcher \"$1\" \"$2\" </dev/null &\n"
+        ));
+        assert!(!script.contains("secret") && !script.contains("user@meo.pt"));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+/// Broad category of a failure, used to decide whether retrying can help
+/// and which exit code to report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// The portal rejected the credentials or they are malformed.
+    Auth,
+    /// The network, hotspot or portal could not be reached.
+    Network,
+    /// A required tool, such as `nmcli`, is not installed.
+    BackendMissing,
+    /// The portal answered something we don't understand.
+    Protocol,
+    /// The portal could not prove it is who we expect, so credentials
+    /// were withheld.
+    Security,
+    /// Anything else, usually a bug or an unexpected local state.
+    Internal,
+}
+
+impl Kind {
+
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::Network | Self::Protocol)
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Internal => 1,
+            Self::Auth => 3,
+            Self::Network => 4,
+            Self::BackendMissing => 5,
+            Self::Protocol => 6,
+            Self::Security => 7,
+        }
+    }
+
+}
+
+/// Invalid command-line arguments or configuration, as opposed to a
+/// failure while running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Usage(pub String);
+
+impl std::error::Error for Usage {}
+
+impl std::fmt::Display for Usage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Cheaply clonable wrapper around an underlying error, so the error enums
+/// can keep the original cause while staying `Clone` and comparable.
+#[derive(Debug, Clone)]
+pub struct Source(Arc<dyn std::error::Error + Send + Sync>);
+
+impl Source {
+    pub fn new(error: impl std::error::Error + Send + Sync + 'static) -> Self {
+        Self(Arc::new(error))
+    }
+
+    pub fn inner(&self) -> &(dyn std::error::Error + 'static) {
+        self.0.as_ref()
+    }
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl PartialEq for Source {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_string() == other.0.to_string()
+    }
+}
+
+impl Eq for Source {}
@@ -0,0 +1,153 @@
+use std::time::{Duration, Instant, SystemTime};
+use std::thread;
+use crate::control::Control;
+use super::notify::Trait as Notify;
+
+pub (crate) trait Trait {
+    fn now(&self) -> Instant;
+    /// Wall clock time, for deadlines that must survive a restart.
+    fn system_time(&self) -> SystemTime;
+    fn sleep(&self, duration: Duration);
+    /// Sleeps like `sleep`, but wakes up early once `control` has a
+    /// command queued.
+    fn wait(&self, duration: Duration, _control: &Control) {
+        self.sleep(duration)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct System;
+
+impl Trait for System {
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+
+    fn wait(&self, duration: Duration, control: &Control) {
+        control.wait(duration)
+    }
+
+}
+
+/// System clock that keeps the service manager watchdog fed while it
+/// sleeps, for waits outside the executor loop such as WISPr polls.
+#[derive(Debug)]
+pub struct Watchdog<N: Notify> {
+    notifier: N,
+    interval: Duration
+}
+
+impl<N: Notify> Watchdog<N> {
+
+    /// `None` when `notifier` has no watchdog to feed.
+    pub fn new(notifier: N) -> Option<Self> {
+        let interval = notifier.watchdog()? / 2;
+        Some(Self { notifier, interval })
+    }
+
+}
+
+impl<N: Notify> Trait for Watchdog<N> {
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        let mut remaining = duration;
+        while !remaining.is_zero() {
+            let chunk = remaining.min(self.interval);
+            thread::sleep(chunk);
+            remaining -= chunk;
+            self.notifier.notify("WATCHDOG=1");
+        }
+    }
+
+}
+
+/// Clock that only advances when slept on, so loops run instantly.
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct Virtual {
+    origin: Instant,
+    system_origin: SystemTime,
+    elapsed: std::sync::Arc<std::sync::Mutex<Duration>>,
+    journal: crate::testing::Journal
+}
+
+#[cfg(test)]
+impl Virtual {
+
+    pub fn new(journal: &crate::testing::Journal) -> Self {
+        Self {
+            origin: Instant::now(),
+            system_origin: SystemTime::now(),
+            elapsed: Default::default(),
+            journal: journal.clone()
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+
+}
+
+#[cfg(test)]
+impl Trait for Virtual {
+
+    fn now(&self) -> Instant {
+        self.origin + self.elapsed()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.system_origin + self.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+        self.journal.record(crate::testing::Event::Sleep(duration));
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl Notify for Recorder {
+        fn notify(&self, state: &str) {
+            self.0.lock().unwrap().push(state.to_string());
+        }
+
+        fn watchdog(&self) -> Option<Duration> {
+            Some(Duration::from_millis(20))
+        }
+    }
+
+    #[test]
+    fn watchdog_clock_feeds_watchdog_while_sleeping() {
+        let clock = Watchdog::new(Recorder(Mutex::new(Vec::new()))).unwrap();
+
+        clock.sleep(Duration::from_millis(25));
+
+        assert_eq!(*clock.notifier.0.lock().unwrap(), vec!["WATCHDOG=1"; 3]);
+        assert!(Watchdog::new(|_: &str| {}).is_none());
+    }
+}
@@ -0,0 +1,153 @@
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use crate::connections::{Connectivity, Trait as Connections};
+
+pub (crate) trait Trait {
+    fn is_online(&self) -> bool;
+}
+
+impl<F: Fn() -> bool> Trait for F {
+    fn is_online(&self) -> bool {
+        self()
+    }
+}
+
+/// Considers the machine online when any of the hosts accepts a TCP
+/// connection. Cheap, but a captive portal that answers every port fools it.
+#[derive(Debug, Clone)]
+pub struct Tcp {
+    hosts: Vec<String>,
+    timeout: Duration
+}
+
+impl Tcp {
+
+    pub fn new() -> Self {
+        const TIMEOUT: Duration = Duration::from_millis(100);
+        const TEST_HOSTS: [&str; 3] = [
+            "1.1.1.1:80",
+            "8.8.8.8:53",
+            "google.com:80"
+        ];
+        Self {
+            hosts: TEST_HOSTS.iter().map(|h| h.to_string()).collect(),
+            timeout: TIMEOUT
+        }
+    }
+
+    fn reachable(&self, host: &str) -> bool {
+        host
+            .to_socket_addrs()
+            .map(|mut addrs| addrs.any(|addr| TcpStream::connect_timeout(&addr, self.timeout).is_ok()))
+            .unwrap_or(false)
+    }
+
+}
+
+impl Default for Tcp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Trait for Tcp {
+    fn is_online(&self) -> bool {
+        self.hosts.iter().any(|host| self.reachable(host))
+    }
+}
+
+/// Considers the machine online only when a well-known endpoint answers
+/// with `204 No Content`, which a captive portal redirect never does.
+#[derive(Debug, Clone)]
+pub struct Http {
+    url: String,
+    timeout: Duration
+}
+
+impl Http {
+
+    pub fn new() -> Self {
+        const URL: &str = "http://connectivitycheck.gstatic.com/generate_204";
+        const TIMEOUT: Duration = Duration::from_secs(2);
+        Self {
+            url: URL.to_string(),
+            timeout: TIMEOUT
+        }
+    }
+
+}
+
+impl Default for Http {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Trait for Http {
+    fn is_online(&self) -> bool {
+        ureq::AgentBuilder::new()
+            .redirects(0)
+            .timeout(self.timeout)
+            .build()
+            .get(&self.url)
+            .call()
+            .map(|response| response.status() == 204)
+            .unwrap_or(false)
+    }
+}
+
+/// Takes the connectivity state NetworkManager last found with its own
+/// checks, without probing anything, and only counts `full` as online.
+/// When NetworkManager doesn't know, e.g. with its checks disabled, the
+/// fallback probe decides.
+pub struct Network<'a, C: Connections> {
+    connections: &'a C,
+    fallback: Box<dyn Trait + 'a>
+}
+
+impl<'a, C: Connections> Network<'a, C> {
+
+    pub fn new(connections: &'a C) -> Self {
+        Self {
+            connections,
+            fallback: Box::new(Tcp::new())
+        }
+    }
+
+    pub fn with_fallback(mut self, fallback: impl Trait + 'a) -> Self {
+        self.fallback = Box::new(fallback);
+        self
+    }
+
+}
+
+impl<C: Connections> Trait for Network<'_, C> {
+    fn is_online(&self) -> bool {
+        match self.connections.connectivity() {
+            Some(state) => state == Connectivity::Full,
+            None => self.fallback.is_online()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connections::Fake as FakeConnections;
+    use crate::testing::Journal;
+
+    #[test]
+    fn network_probe_trusts_network_manager_state() {
+        let connections = FakeConnections::new(&Journal::new());
+        let probe = Network::new(&connections).with_fallback(|| true);
+
+        connections.set_connectivity(Some(Connectivity::Full));
+        assert!(probe.is_online());
+        connections.set_connectivity(Some(Connectivity::Portal));
+        assert!(!probe.is_online());
+        connections.set_connectivity(Some(Connectivity::Limited));
+        assert!(!probe.is_online());
+        connections.set_connectivity(None);
+        assert!(probe.is_online());
+    }
+}
@@ -1,39 +1,43 @@
-use super::{Oneshot, Error, Trait};
+use super::{Oneshot, Error, Guard, Limiter};
+use super::clock::{self, Trait as Clock};
+use super::connectivity::{self, Trait as Connectivity};
+use super::notify::{self, Trait as Notify};
+use super::retry;
+use crate::control::{Command, Control, Handover};
 use crate::log;
-use crate::session::Trait as Session;
+use crate::session::{Error as SessionError, Trait as Session, Transport};
+use crate::session::captive::{self, CaptiveApi};
 use crate::connections::Trait as Connections;
 use crate::connections::Error as ConnectionError;
-use std::time::Duration;
-use std::thread;
+use crate::connections::Connectivity as NetworkState;
+use std::cell::{Cell, RefCell};
+use std::time::{Duration, Instant};
 
 pub struct Continuous<'a, C: Connections, S: Session> {
     connections: &'a C,
     session: &'a S,
-    target: &'a str,
+    target: RefCell<String>,
     original: Option<String>,
     check_interval: Duration,
-    oneshot: Oneshot<'a, C, S>
+    retry: retry::Policy,
+    captive_api: Option<captive::Discovery>,
+    transport: Transport,
+    relogin_margin: Duration,
+    max_session: Option<Duration>,
+    session_started: Cell<Option<Instant>>,
+    control: Option<Control>,
+    paused: Cell<bool>,
+    oneshot: Oneshot<'a, C, S>,
+    clock: Box<dyn Clock + 'a>,
+    connectivity: Box<dyn Connectivity + 'a>,
+    notifier: Box<dyn Notify + 'a>
 }
 
 impl<'a, C: Connections, S: Session> Continuous<'a, C, S> {
 
-    fn has_internet_connection() -> bool {
-        const TIMEOUT: std::time::Duration = std::time::Duration::from_millis(100);
-        const TEST_HOSTS: [&str; 3] = [
-            "1.1.1.1:80", 
-            "8.8.8.8:53", 
-            "google.com:80"
-        ];
-        TEST_HOSTS
-            .iter()
-            .any(|&host| {
-                std::net::TcpStream::connect_timeout(&host.parse().unwrap(), TIMEOUT)
-                    .is_ok()
-            })
-    }
-
     pub fn new(connections: &'a C, session: &'a S, target: &'a str) -> Self {
         const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+        const DEFAULT_RELOGIN_MARGIN: Duration = Duration::from_secs(60);
         let original = connections.active();
         let oneshot = Oneshot::new(
             connections, 
@@ -43,37 +47,316 @@ impl<'a, C: Connections, S: Session> Continuous<'a, C, S> {
         Self {
             connections,
             session,
-            target,
+            target: RefCell::new(target.to_string()),
             original,
             check_interval: DEFAULT_INTERVAL,
-            oneshot
+            retry: retry::Policy::new(),
+            captive_api: Some(captive::Discovery::Dhcp),
+            transport: Transport::default(),
+            relogin_margin: DEFAULT_RELOGIN_MARGIN,
+            max_session: None,
+            session_started: Cell::new(None),
+            control: None,
+            paused: Cell::new(false),
+            oneshot,
+            clock: Box::new(clock::System),
+            connectivity: Box::new(connectivity::Tcp::new()),
+            notifier: Box::new(notify::Silent)
         }
     }
 
+    #[allow(dead_code)]
+    pub fn with_clock(mut self, clock: impl Clock + 'a) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    pub fn with_connectivity(mut self, connectivity: impl Connectivity + 'a) -> Self {
+        self.connectivity = Box::new(connectivity);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_retry(mut self, retry: retry::Policy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_captive_api(mut self, captive_api: Option<captive::Discovery>) -> Self {
+        self.captive_api = captive_api;
+        self
+    }
+
+    /// Network settings the Captive Portal API is reached with.
+    pub fn with_transport(mut self, transport: &Transport) -> Self {
+        self.transport = transport.clone();
+        self
+    }
+
+    /// How long before the session expires it is renewed with a logout
+    /// and login cycle.
+    pub fn with_relogin_margin(mut self, margin: Duration) -> Self {
+        self.relogin_margin = margin;
+        self
+    }
+
+    /// Session length to assume when the portal does not report how much
+    /// time is left, counted from the last login seen by this executor.
+    pub fn with_max_session(mut self, max_session: Option<Duration>) -> Self {
+        self.max_session = max_session;
+        self
+    }
+
+    /// Checks the hotspot must pass before every login, including the
+    /// ones done by the inner oneshot executor.
+    pub fn with_guard(mut self, guard: Guard) -> Self {
+        self.oneshot = self.oneshot.with_guard(guard);
+        self
+    }
+
+    /// Limits login attempts, here and in the inner oneshot executor.
+    #[allow(dead_code)]
+    pub fn with_limiter(mut self, limiter: Limiter) -> Self {
+        self.oneshot = self.oneshot.with_limiter(limiter);
+        self
+    }
+
+    /// Takes commands from `control` between checks. The loop returns
+    /// `Ok(())` once asked to reload the configuration, handing its target,
+    /// original connection and paused state over to the next loop taking
+    /// commands from `control`.
+    pub fn with_control(mut self, control: Control) -> Self {
+        if let Some(handover) = control.take_handover() {
+            *self.target.borrow_mut() = handover.target;
+            self.original = handover.original;
+            self.paused.set(handover.paused);
+        }
+        self.control = Some(control);
+        self
+    }
+
+    pub fn with_notifier(mut self, notifier: impl Notify + 'a) -> Self {
+        self.notifier = Box::new(notifier);
+        self
+    }
+
+    fn target(&self) -> String {
+        self.target.borrow().clone()
+    }
+
+    /// Sleeps, waking up early when a control command comes in and
+    /// keeping the service manager watchdog fed meanwhile.
+    fn wait(&self, duration: Duration) {
+        let watchdog = self.notifier.watchdog();
+        let step = watchdog.map_or(duration, |watchdog| (watchdog / 2).max(Duration::from_secs(1)));
+        let mut remaining = duration;
+        loop {
+            let chunk = remaining.min(step);
+            match &self.control {
+                Some(control) => self.clock.wait(chunk, control),
+                None => self.clock.sleep(chunk)
+            }
+            remaining -= chunk;
+            self.feed_watchdog();
+            if remaining.is_zero() || self.control.as_ref().is_some_and(Control::has_pending) {
+                break;
+            }
+        }
+    }
+
+    /// Tells the service manager the loop is still making progress, between
+    /// steps that may each block for a while.
+    fn feed_watchdog(&self) {
+        if self.notifier.watchdog().is_some() {
+            self.notifier.notify("WATCHDOG=1");
+        }
+    }
+
+    /// Tells the service manager the session is up.
+    fn report_online(&self) {
+        let mut status = format!("STATUS=Online on {}", self.target());
+        if let Some(account) = self.session.account() {
+            status.push_str(&format!(" as {}", account));
+        }
+        self.notifier.notify(&status);
+    }
+
+    fn status(&self) -> String {
+        let mut status = vec![
+            format!("target: {}", self.target()),
+            format!("state: {}", if self.paused.get() { "paused" } else { "running" }),
+            format!("session: {}", if self.session.is_logged() { "logged in" } else { "logged out" }),
+        ];
+        if let Some(account) = self.session.account() {
+            status.push(format!("account: {}", account));
+        }
+        if let Some(limits) = self.session.limits().or_else(|| self.oneshot.limiter().map(Limiter::to_string)) {
+            status.push(format!("logins: {}", limits));
+        }
+        status.join("\n")
+    }
+
+    fn force_relogin(&self) -> String {
+        let _ = self.session.logout().map_err(log::error);
+        match self.oneshot.login(&self.target()) {
+            Ok(()) => {
+                self.session_started.set(Some(self.clock.now()));
+                "Logged in".to_string()
+            }
+            Err(error) => format!("error: {}", error)
+        }
+    }
+
+    /// Handles the queued control commands and returns whether the
+    /// configuration should be reloaded.
+    fn handle_commands(&self) -> bool {
+        let Some(control) = &self.control else {
+            return false;
+        };
+        let mut reload = false;
+        while let Some(request) = control.take() {
+            log::info(&format!("Control command: {}", request.command));
+            let reply = match &request.command {
+                Command::Status => self.status(),
+                Command::ForceRelogin => self.force_relogin(),
+                Command::Pause => {
+                    self.paused.set(true);
+                    "Paused".to_string()
+                }
+                Command::Resume => {
+                    self.paused.set(false);
+                    "Resumed".to_string()
+                }
+                Command::Logout => {
+                    self.paused.set(true);
+                    match self.session.logout() {
+                        Ok(()) => "Logged out, paused until resume".to_string(),
+                        Err(error) => format!("error: {}", error)
+                    }
+                }
+                Command::SwitchTarget(target) => {
+                    *self.target.borrow_mut() = target.clone();
+                    format!("Switched target to {}", target)
+                }
+                Command::ReloadConfig => {
+                    reload = true;
+                    "Reloading configuration".to_string()
+                }
+            };
+            request.reply(reply);
+        }
+        reload
+    }
+
+    fn has_internet_connection(&self) -> bool {
+        self.connectivity.is_online()
+    }
+
+    /// Brings the connection active before the executor started back up.
+    /// NetworkManager may have switched back to it on its own meanwhile,
+    /// which counts as restored rather than hiding the original error.
     fn restore_original_connection(&self) -> Result<(), Error> {
         match &self.original {
-            Some(connection) => self.connections.connect(&connection).map_err(|e| e.into()),
-            None => self.connections.disconnect(&self.target).map_err(|e| e.into()),
+            Some(connection) => match self.connections.connect(connection) {
+                Err(ConnectionError::AlreadyActive) => Ok(()),
+                result => result.map_err(|e| e.into()),
+            },
+            None => self.connections.disconnect(&self.target()).map_err(|e| e.into()),
         }
     }
 
     fn reconnect_and_login(&self) -> Result<(), Error> {
         log::info("No internet connection, attempting to reconnect");
         let _ = self.session.logout().map_err(log::error);
+        self.feed_watchdog();
         self.connections.reconnect()?;
         log::info("Connection reconnected");
-        let _ = self.session.login().map_err(log::error);
+        self.feed_watchdog();
+        self.login()?;
         log::info("Login attempt completed");
         Ok(())
     }
 
+    fn captive_status(&self) -> Option<captive::Status> {
+        let url = match self.captive_api.as_ref()? {
+            captive::Discovery::Url(url) => url.clone(),
+            captive::Discovery::Dhcp => self.connections.captive_portal_api(&self.target())?,
+        };
+        let api = CaptiveApi::new(&url).map_err(log::error).ok()??;
+        api.with_transport(&self.transport).status().map_err(log::error).ok()
+    }
+
+    /// Failed logins are only logged, as the next check tries again,
+    /// unless the limiter locked the account.
+    fn login(&self) -> Result<(), Error> {
+        match self.oneshot.login(&self.target()) {
+            Ok(()) => self.session_started.set(Some(self.clock.now())),
+            Err(error @ SessionError::Locked(_)) => return Err(error.into()),
+            Err(SessionError::Untrusted(_)) => {}
+            Err(error) => log::error(error)
+        }
+        Ok(())
+    }
+
+    fn relogin(&self) -> Result<(), Error> {
+        let _ = self.session.logout().map_err(log::error);
+        self.login()
+    }
+
+    /// Time left in the current session according to the configured
+    /// maximum session length.
+    fn estimated_remaining(&self) -> Option<Duration> {
+        let max_session = self.max_session?;
+        let started = self.session_started.get().unwrap_or_else(|| self.clock.now());
+        self.session_started.set(Some(started));
+        Some(max_session.saturating_sub(self.clock.now() - started))
+    }
+
+    /// Keeps the session alive, logging in when the portal reports access
+    /// was revoked or the session is about to expire, and returns how long
+    /// to wait until the next check.
+    fn maintain_session(&self) -> Result<Duration, Error> {
+        let status = self.captive_status();
+        if status.as_ref().is_some_and(|status| status.captive) {
+            log::warn("Captive portal reports no access, logging in");
+            self.login()?;
+            return Ok(self.check_interval);
+        }
+
+        let remaining = status
+            .and_then(|status| status.seconds_remaining)
+            .map(Duration::from_secs)
+            .or_else(|| self.estimated_remaining());
+
+        Ok(match remaining {
+            Some(remaining) if remaining <= self.relogin_margin => {
+                log::info("Session about to expire, logging in again");
+                self.relogin()?;
+                self.check_interval
+            }
+            Some(remaining) => self.check_interval.min(remaining - self.relogin_margin),
+            None => self.check_interval
+        })
+    }
+
     fn ensure_connectivity(&self) -> Result<(), Error> {
-        self.oneshot.execute()?;
-        if Self::has_internet_connection() {
+        let logins = self.oneshot.logins();
+        self.oneshot.run(&self.target())?;
+        if self.oneshot.logins() != logins {
+            self.session_started.set(Some(self.clock.now()));
+        }
+        self.feed_watchdog();
+        if self.connections.connectivity() == Some(NetworkState::Portal) {
+            log::warn("NetworkManager reports a captive portal, logging in");
+            self.login()?;
+            self.feed_watchdog();
+        }
+        if self.has_internet_connection() {
             return Ok(());
         }
+        self.feed_watchdog();
         self.reconnect_and_login()?;
-        if !Self::has_internet_connection() {
+        if !self.has_internet_connection() {
             return Err(Error::Connection(ConnectionError::NoInternet));
         }
         Ok(())
@@ -82,13 +365,736 @@ impl<'a, C: Connections, S: Session> Continuous<'a, C, S> {
 
 impl<C: Connections, S: Session> super::Trait for Continuous<'_, C, S> {
     fn execute(&self) -> Result<(), Error> {
+        let mut failures = 0;
+        let mut failing_since = None;
+        // Ready as soon as commands are taken, whether or not the hotspot
+        // is in range yet; the status line tells how the session is doing.
+        self.notifier.notify(&format!("READY=1\nSTATUS=Connecting to {}", self.target()));
         loop {
-            if let Err(error) = self.ensure_connectivity() {
+            if self.handle_commands() {
+                self.notifier.notify("RELOADING=1\nSTATUS=Reloading configuration");
+                if let Some(control) = &self.control {
+                    control.hand_over(Handover {
+                        target: self.target(),
+                        original: self.original.clone(),
+                        paused: self.paused.get()
+                    });
+                }
+                return Ok(());
+            }
+            if self.paused.get() {
+                self.notifier.notify("STATUS=Paused");
+                self.wait(self.check_interval);
+                continue;
+            }
+
+            let error = match self.ensure_connectivity().and_then(|_| self.maintain_session()) {
+                Ok(delay) => {
+                    failures = 0;
+                    failing_since = None;
+                    self.report_online();
+                    self.wait(delay);
+                    continue;
+                }
+                Err(error) => error
+            };
+
+            failures += 1;
+            let failing_for = self.clock.now() - *failing_since.get_or_insert(self.clock.now());
+            if error.is_fatal() || self.retry.is_exhausted(failures, failing_for) {
+                self.notifier.notify(&format!("STOPPING=1\nSTATUS={}", error));
                 self.restore_original_connection()?;
                 log::info("Original connection restored");
-                return Err(error.into());
+                return Err(error);
+            }
+
+            let delay = self.retry.delay(failures);
+            self.notifier.notify(&format!("STATUS={}, retrying (attempt {})", error, failures));
+            log::warn(&format!("{}, retrying in {}s (attempt {})", error, delay.as_secs(), failures));
+            self.wait(delay);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::clock::Virtual;
+    use super::super::Trait;
+    use crate::connections::{Error as ConnectionError, Fake as FakeConnections};
+    use crate::session::Fake as FakeSession;
+    use crate::testing::{Event, Journal};
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    const TARGET: &str = "MEO-WiFi";
+    const INTERVAL: Duration = Duration::from_secs(60);
+
+    fn scripted(results: &[bool]) -> impl Fn() -> bool {
+        let results = RefCell::new(results.iter().copied().collect::<VecDeque<_>>());
+        move || results.borrow_mut().pop_front().unwrap_or(false)
+    }
+
+    #[test]
+    fn sleeps_between_healthy_checks() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal).logged(true);
+        let clock = Virtual::new(&journal);
+
+        let result = Continuous::new(&connections, &session, TARGET)
+            .with_clock(clock.clone())
+            .with_retry(retry::Policy::disabled())
+            .with_connectivity(scripted(&[true, true, true]))
+            .execute();
+
+        assert_eq!(result, Err(Error::Connection(ConnectionError::NoInternet)));
+        assert_eq!(clock.elapsed(), INTERVAL * 3);
+        assert_eq!(journal.events()[..3], vec![Event::Sleep(INTERVAL); 3]);
+    }
+
+    #[test]
+    fn reconnects_and_logs_in_again_when_internet_drops() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal).logged(true);
+
+        let result = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Virtual::new(&journal))
+            .with_retry(retry::Policy::disabled())
+            .with_connectivity(scripted(&[false, true]))
+            .execute();
+
+        assert_eq!(result, Err(Error::Connection(ConnectionError::NoInternet)));
+        assert_eq!(&journal.events()[..5], &[
+            Event::Logout,
+            Event::Disconnect(TARGET.into()),
+            Event::Connect(TARGET.into()),
+            Event::Login,
+            Event::Sleep(INTERVAL),
+        ]);
+    }
+
+    #[test]
+    fn restores_original_connection_on_failure() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal)
+            .with_profile("Home", true)
+            .with_profile(TARGET, false);
+        let session = FakeSession::new(&journal);
+        let continuous = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Virtual::new(&journal))
+            .with_retry(retry::Policy::disabled())
+            .with_connectivity(scripted(&[]));
+
+        let result = continuous.execute();
+
+        assert_eq!(result, Err(Error::Connection(ConnectionError::NoInternet)));
+        assert_eq!(journal.events(), vec![
+            Event::Disconnect("Home".into()),
+            Event::Connect(TARGET.into()),
+            Event::Login,
+            Event::Logout,
+            Event::Disconnect(TARGET.into()),
+            Event::Connect(TARGET.into()),
+            Event::Login,
+            Event::Disconnect(TARGET.into()),
+            Event::Connect("Home".into()),
+        ]);
+        assert!(connections.is_connected("Home"));
+    }
+
+    #[test]
+    fn disconnects_target_when_there_was_no_original_connection() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, false);
+        let session = FakeSession::new(&journal);
+
+        let result = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Virtual::new(&journal))
+            .with_retry(retry::Policy::disabled())
+            .with_connectivity(scripted(&[]))
+            .execute();
+
+        assert_eq!(result, Err(Error::Connection(ConnectionError::NoInternet)));
+        assert_eq!(journal.events().last(), Some(&Event::Disconnect(TARGET.into())));
+        assert!(!connections.is_connected(TARGET));
+    }
+
+    #[test]
+    fn stops_when_target_cannot_be_reconnected() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal)
+            .with_profile(TARGET, true);
+        let session = FakeSession::new(&journal).logged(true);
+        connections.fail_connect(TARGET, ConnectionError::Unavailable);
+
+        let result = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Virtual::new(&journal))
+            .with_retry(retry::Policy::disabled())
+            .with_connectivity(scripted(&[false]))
+            .execute();
+
+        assert_eq!(result, Err(Error::Connection(ConnectionError::Unavailable)));
+        assert!(!journal.events().contains(&Event::Login));
+    }
+
+    #[test]
+    fn ignores_logout_failure_while_reconnecting() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal).logged(true);
+        session.fail_logout(crate::session::Error::NetworkUnreachable);
+
+        let result = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Virtual::new(&journal))
+            .with_retry(retry::Policy::disabled())
+            .with_connectivity(scripted(&[false, true]))
+            .execute();
+
+        assert_eq!(result, Err(Error::Connection(ConnectionError::NoInternet)));
+        assert_eq!(&journal.events()[..4], &[
+            Event::Logout,
+            Event::Disconnect(TARGET.into()),
+            Event::Connect(TARGET.into()),
+            Event::Login,
+        ]);
+    }
+
+    #[test]
+    fn brings_target_back_up_when_it_drops_between_checks() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal).logged(true);
+        let probe = {
+            let connections = connections.clone();
+            let checks = RefCell::new(0);
+            move || {
+                *checks.borrow_mut() += 1;
+                if *checks.borrow() == 1 {
+                    connections.set_active(TARGET, false);
+                }
+                *checks.borrow() <= 2
             }
-            thread::sleep(self.check_interval);
+        };
+
+        let result = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Virtual::new(&journal))
+            .with_retry(retry::Policy::disabled())
+            .with_connectivity(probe)
+            .execute();
+
+        assert_eq!(result, Err(Error::Connection(ConnectionError::NoInternet)));
+        assert_eq!(&journal.events()[..2], &[
+            Event::Sleep(INTERVAL),
+            Event::Connect(TARGET.into()),
+        ]);
+    }
+
+    #[test]
+    fn propagates_failure_to_bring_target_down() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal).logged(true);
+        connections.fail_disconnect(TARGET, ConnectionError::Command("busy".into()));
+
+        let result = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Virtual::new(&journal))
+            .with_retry(retry::Policy::disabled())
+            .with_connectivity(scripted(&[false]))
+            .execute();
+
+        assert_eq!(result, Err(Error::Connection(ConnectionError::Command("busy".into()))));
+        assert_eq!(journal.events(), vec![Event::Logout]);
+    }
+
+    #[test]
+    fn reports_restore_failure_over_original_error() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal)
+            .with_profile("Home", true)
+            .with_profile(TARGET, false);
+        let session = FakeSession::new(&journal);
+        connections.fail_connect("Home", ConnectionError::Unavailable);
+
+        let result = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Virtual::new(&journal))
+            .with_retry(retry::Policy::disabled())
+            .with_connectivity(scripted(&[]))
+            .execute();
+
+        assert_eq!(result, Err(Error::Connection(ConnectionError::Unavailable)));
+        assert!(connections.is_connected(TARGET));
+    }
+
+    #[test]
+    fn accepts_original_connection_that_came_back_on_its_own() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal)
+            .with_profile("Home", true)
+            .with_profile(TARGET, false);
+        let session = FakeSession::new(&journal);
+        let probe = {
+            let connections = connections.clone();
+            move || {
+                connections.set_active(TARGET, false);
+                connections.set_active("Home", true);
+                false
+            }
+        };
+
+        let result = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Virtual::new(&journal))
+            .with_retry(retry::Policy::disabled())
+            .with_connectivity(probe)
+            .execute();
+
+        assert_eq!(result, Err(Error::Connection(ConnectionError::NoInternet)));
+        assert!(connections.is_connected("Home"));
+    }
+
+    #[test]
+    fn retries_transient_failures_with_backoff() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal).logged(true);
+        let retry = retry::Policy::new()
+            .with_delays(Duration::from_secs(5), Duration::from_secs(60))
+            .with_jitter(0.0)
+            .with_max_attempts(3);
+
+        let result = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Virtual::new(&journal))
+            .with_retry(retry)
+            .with_connectivity(scripted(&[false, false, false, false, true]))
+            .execute();
+
+        assert_eq!(result, Err(Error::Connection(ConnectionError::NoInternet)));
+        let sleeps: Vec<_> = journal
+            .events()
+            .into_iter()
+            .filter_map(|e| match e {
+                Event::Sleep(d) => Some(d.as_secs()),
+                _ => None
+            })
+            .collect();
+        assert_eq!(sleeps, vec![5, 10, 60, 5, 10]);
+    }
+
+    #[test]
+    fn gives_up_when_retry_budget_is_spent() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal)
+            .with_profile("Home", true)
+            .with_profile(TARGET, false);
+        let session = FakeSession::new(&journal);
+        let clock = Virtual::new(&journal);
+        let retry = retry::Policy::new()
+            .with_delays(Duration::from_secs(10), Duration::from_secs(10))
+            .with_jitter(0.0)
+            .with_max_attempts(100)
+            .with_max_duration(Duration::from_secs(30));
+
+        let result = Continuous::new(&connections, &session, TARGET)
+            .with_clock(clock.clone())
+            .with_retry(retry)
+            .with_connectivity(scripted(&[]))
+            .execute();
+
+        assert_eq!(result, Err(Error::Connection(ConnectionError::NoInternet)));
+        assert_eq!(clock.elapsed(), Duration::from_secs(30));
+        assert!(connections.is_connected("Home"));
+    }
+
+    #[test]
+    fn does_not_retry_fatal_errors() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal);
+        session.fail_login(crate::session::Error::CredentialsMismatch);
+
+        let result = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Virtual::new(&journal))
+            .with_connectivity(scripted(&[]))
+            .execute();
+
+        assert_eq!(result, Err(Error::Session(crate::session::Error::CredentialsMismatch)));
+        assert_eq!(journal.events(), vec![Event::Login]);
+    }
+
+    fn captive_api(seconds_remaining: u64, captive: bool) -> crate::testing::Server {
+        let body = format!(r#"{{"captive": {}, "seconds-remaining": {}}}"#, captive, seconds_remaining);
+        crate::testing::Server::start(move |_| crate::testing::Response::ok(&body))
+    }
+
+    #[test]
+    fn checks_again_shortly_before_session_expires() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal).logged(true);
+        let server = captive_api(90, false);
+        connections.set_captive_portal_api(Some(server.url("/capport")));
+
+        let _ = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Virtual::new(&journal))
+            .with_retry(retry::Policy::disabled())
+            .with_connectivity(scripted(&[true]))
+            .execute();
+
+        assert_eq!(journal.events()[0], Event::Sleep(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn logs_in_again_when_session_is_about_to_expire() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal).logged(true);
+        let server = captive_api(45, false);
+
+        let _ = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Virtual::new(&journal))
+            .with_retry(retry::Policy::disabled())
+            .with_captive_api(Some(captive::Discovery::Url(server.url("/capport"))))
+            .with_connectivity(scripted(&[true]))
+            .execute();
+
+        assert_eq!(&journal.events()[..3], &[Event::Logout, Event::Login, Event::Sleep(INTERVAL)]);
+    }
+
+    #[test]
+    fn logs_in_when_portal_reports_captive_state() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal).logged(true);
+        let server = captive_api(0, true);
+        connections.set_captive_portal_api(Some(server.url("/capport")));
+
+        let _ = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Virtual::new(&journal))
+            .with_retry(retry::Policy::disabled())
+            .with_connectivity(scripted(&[true]))
+            .execute();
+
+        assert_eq!(&journal.events()[..2], &[Event::Login, Event::Sleep(INTERVAL)]);
+    }
+
+    #[test]
+    fn logs_in_when_network_manager_reports_portal() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        connections.set_connectivity(Some(NetworkState::Portal));
+        let session = FakeSession::new(&journal).logged(true);
+
+        let _ = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Virtual::new(&journal))
+            .with_retry(retry::Policy::disabled())
+            .with_connectivity(scripted(&[true]))
+            .execute();
+
+        assert_eq!(&journal.events()[..2], &[Event::Login, Event::Sleep(INTERVAL)]);
+    }
+
+    #[test]
+    fn skips_captive_login_on_untrusted_access_point() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        connections.set_bssid(Some("66:77:88:99:AA:BB"));
+        let session = FakeSession::new(&journal).logged(true);
+        let server = captive_api(0, true);
+        connections.set_captive_portal_api(Some(server.url("/capport")));
+
+        let _ = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Virtual::new(&journal))
+            .with_retry(retry::Policy::disabled())
+            .with_guard(Guard::default().with_ouis(&["00:1A:2B".to_string()]))
+            .with_connectivity(scripted(&[true]))
+            .execute();
+
+        assert_eq!(journal.events()[0], Event::Sleep(INTERVAL));
+    }
+
+    #[test]
+    fn holds_off_captive_login_after_invalid_ip() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal).logged(true);
+        session.fail_login(SessionError::InvalidIp);
+        let server = captive_api(0, true);
+        connections.set_captive_portal_api(Some(server.url("/capport")));
+
+        let _ = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Virtual::new(&journal))
+            .with_retry(retry::Policy::disabled())
+            .with_limiter(Limiter::default())
+            .with_connectivity(scripted(&[true, true]))
+            .execute();
+
+        assert_eq!(&journal.events()[..3], &[Event::Login, Event::Sleep(INTERVAL), Event::Sleep(INTERVAL)]);
+    }
+
+    #[test]
+    fn renews_session_before_configured_length_runs_out() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal).logged(true);
+
+        let _ = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Virtual::new(&journal))
+            .with_retry(retry::Policy::disabled())
+            .with_captive_api(None)
+            .with_max_session(Some(Duration::from_secs(300)))
+            .with_connectivity(scripted(&[true; 6]))
+            .execute();
+
+        assert_eq!(&journal.events()[..7], &[
+            Event::Sleep(INTERVAL),
+            Event::Sleep(INTERVAL),
+            Event::Sleep(INTERVAL),
+            Event::Sleep(INTERVAL),
+            Event::Logout,
+            Event::Login,
+            Event::Sleep(INTERVAL),
+        ]);
+    }
+
+    #[test]
+    fn counts_session_length_from_login_after_session_dropped() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal).logged(true);
+        let probe = {
+            let session = session.clone();
+            let checks = RefCell::new(0);
+            move || {
+                *checks.borrow_mut() += 1;
+                if *checks.borrow() == 1 {
+                    session.set_logged(false);
+                }
+                *checks.borrow() <= 2
+            }
+        };
+
+        let _ = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Virtual::new(&journal))
+            .with_retry(retry::Policy::disabled())
+            .with_max_session(Some(Duration::from_secs(120)))
+            .with_relogin_margin(Duration::from_secs(30))
+            .with_connectivity(probe)
+            .execute();
+
+        assert_eq!(&journal.events()[..3], &[Event::Sleep(INTERVAL), Event::Login, Event::Sleep(INTERVAL)]);
+    }
+
+    #[test]
+    fn wakes_up_early_when_margin_falls_inside_check_interval() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal).logged(true);
+
+        let _ = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Virtual::new(&journal))
+            .with_retry(retry::Policy::disabled())
+            .with_captive_api(None)
+            .with_max_session(Some(Duration::from_secs(100)))
+            .with_relogin_margin(Duration::from_secs(30))
+            .with_connectivity(scripted(&[true, true, true]))
+            .execute();
+
+        assert_eq!(&journal.events()[..4], &[
+            Event::Sleep(Duration::from_secs(60)),
+            Event::Sleep(Duration::from_secs(10)),
+            Event::Logout,
+            Event::Login,
+        ]);
+    }
+
+    /// Virtual clock that sends the next scripted command each time the
+    /// loop sleeps.
+    struct Steered {
+        clock: Virtual,
+        control: Control,
+        script: RefCell<VecDeque<Command>>
+    }
+
+    impl Steered {
+        fn new(journal: &Journal, control: &Control, script: Vec<Command>) -> Self {
+            Self {
+                clock: Virtual::new(journal),
+                control: control.clone(),
+                script: RefCell::new(script.into())
+            }
+        }
+    }
+
+    impl Clock for Steered {
+        fn now(&self) -> Instant {
+            self.clock.now()
+        }
+
+        fn system_time(&self) -> std::time::SystemTime {
+            self.clock.system_time()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.clock.sleep(duration);
+            if let Some(command) = self.script.borrow_mut().pop_front() {
+                self.control.send(command);
+            }
+        }
+    }
+
+    #[test]
+    fn skips_checks_while_paused() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal).logged(true);
+        let control = Control::default();
+        let checks = Cell::new(0);
+        let paused = control.send(Command::Pause);
+
+        let result = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Steered::new(&journal, &control, vec![Command::Resume, Command::ReloadConfig]))
+            .with_control(control.clone())
+            .with_connectivity(|| {
+                checks.set(checks.get() + 1);
+                true
+            })
+            .execute();
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(paused.recv().unwrap(), "Paused");
+        assert_eq!(checks.get(), 1);
+    }
+
+    #[test]
+    fn logs_out_and_reports_status() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal).logged(true);
+        let control = Control::default();
+        let logout = control.send(Command::Logout);
+        let status = control.send(Command::Status);
+
+        let result = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Steered::new(&journal, &control, vec![Command::ReloadConfig]))
+            .with_control(control.clone())
+            .execute();
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(logout.recv().unwrap(), "Logged out, paused until resume");
+        assert_eq!(
+            status.recv().unwrap(),
+            "target: MEO-WiFi\nstate: paused\nsession: logged out"
+        );
+        assert_eq!(journal.events(), vec![Event::Logout, Event::Sleep(INTERVAL)]);
+    }
+
+    #[test]
+    fn switches_to_another_target() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal)
+            .with_profile(TARGET, true)
+            .with_profile("FON", false);
+        let session = FakeSession::new(&journal).logged(true);
+        let control = Control::default();
+        control.send(Command::SwitchTarget("FON".to_string()));
+
+        let result = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Steered::new(&journal, &control, vec![Command::ReloadConfig]))
+            .with_control(control.clone())
+            .with_connectivity(scripted(&[true]))
+            .execute();
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(&journal.events()[..2], &[Event::Disconnect(TARGET.into()), Event::Connect("FON".into())]);
+    }
+
+    #[test]
+    fn keeps_target_original_connection_and_pause_across_reload() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal)
+            .with_profile("Home", true)
+            .with_profile(TARGET, false)
+            .with_profile("FON", false);
+        let session = FakeSession::new(&journal);
+        let control = Control::default();
+        control.send(Command::SwitchTarget("FON".to_string()));
+        control.send(Command::Pause);
+        control.send(Command::ReloadConfig);
+
+        let first = Continuous::new(&connections, &session, TARGET).with_control(control.clone()).execute();
+        connections.set_active("Home", false);
+        connections.set_active("FON", true);
+        let status = control.send(Command::Status);
+        let reloaded = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Steered::new(&journal, &control, vec![Command::Resume]))
+            .with_retry(retry::Policy::disabled())
+            .with_control(control.clone())
+            .with_connectivity(scripted(&[false]));
+
+        assert_eq!(first, Ok(()));
+        assert!(reloaded.execute().is_err());
+        assert_eq!(status.recv().unwrap(), "target: FON\nstate: paused\nsession: logged out");
+        assert_eq!(journal.events().last(), Some(&Event::Connect("Home".into())));
+    }
+
+    #[test]
+    fn notifies_readiness_before_first_check_then_status() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal).logged(true);
+        let notify = |state: &str| journal.record(Event::Notify(state.into()));
+
+        let _ = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Virtual::new(&journal))
+            .with_retry(retry::Policy::disabled())
+            .with_connectivity(scripted(&[true, true]))
+            .with_notifier(notify)
+            .execute();
+
+        let notifications = journal.events().into_iter().filter(|event| matches!(event, Event::Notify(_))).collect::<Vec<_>>();
+        assert_eq!(notifications, vec![
+            Event::Notify("READY=1\nSTATUS=Connecting to MEO-WiFi".into()),
+            Event::Notify("STATUS=Online on MEO-WiFi".into()),
+            Event::Notify("STATUS=Online on MEO-WiFi".into()),
+            Event::Notify("STOPPING=1\nSTATUS=No internet connection".into()),
+        ]);
+    }
+
+    struct Watchdog(Journal);
+
+    impl Notify for Watchdog {
+        fn notify(&self, state: &str) {
+            self.0.record(Event::Notify(state.into()));
+        }
+
+        fn watchdog(&self) -> Option<Duration> {
+            Some(Duration::from_secs(50))
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn feeds_watchdog_between_steps_and_while_sleeping() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal).logged(true);
+
+        let _ = Continuous::new(&connections, &session, TARGET)
+            .with_clock(Virtual::new(&journal))
+            .with_retry(retry::Policy::disabled())
+            .with_connectivity(scripted(&[true]))
+            .with_notifier(Watchdog(journal.clone()))
+            .execute();
+
+        assert_eq!(&journal.events()[..7], &[
+            Event::Notify("READY=1\nSTATUS=Connecting to MEO-WiFi".into()),
+            Event::Notify("WATCHDOG=1".into()),
+            Event::Notify("STATUS=Online on MEO-WiFi".into()),
+            Event::Sleep(Duration::from_secs(25)),
+            Event::Notify("WATCHDOG=1".into()),
+            Event::Sleep(Duration::from_secs(25)),
+            Event::Notify("WATCHDOG=1".into()),
+        ]);
+        assert_eq!(journal.events()[7], Event::Sleep(Duration::from_secs(10)));
+    }
+}
@@ -1,5 +1,6 @@
 use crate::connections::Error as ConnectionError;
 use crate::session::Error as SessionError;
+use crate::error::Kind;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
@@ -7,6 +8,23 @@ pub enum Error {
     Session(SessionError)
 }
 
+impl Error {
+
+    pub fn kind(&self) -> Kind {
+        match self {
+            Self::Connection(err) => err.kind(),
+            Self::Session(err) => err.kind(),
+        }
+    }
+
+    /// Whether retrying cannot possibly help, e.g. the portal rejected the
+    /// credentials or `nmcli` is missing.
+    pub fn is_fatal(&self) -> bool {
+        !self.kind().is_transient()
+    }
+
+}
+
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let msg = match self {
@@ -17,7 +35,14 @@ impl std::fmt::Display for Error {
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Connection(err) => std::error::Error::source(err),
+            Self::Session(err) => std::error::Error::source(err),
+        }
+    }
+}
 
 impl From<ConnectionError> for Error {
     fn from(e: ConnectionError) -> Self {
@@ -29,4 +54,26 @@ impl From<SessionError> for Error {
     fn from(e: SessionError) -> Self {
         Self::Session(e)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_only_network_and_portal_errors() {
+        assert!(Error::from(SessionError::CredentialsMismatch).is_fatal());
+        assert!(Error::from(ConnectionError::BackendMissing("nmcli".into())).is_fatal());
+        assert!(!Error::from(ConnectionError::Unavailable).is_fatal());
+        assert!(!Error::from(SessionError::NetworkUnreachable).is_fatal());
+        assert!(!Error::from(SessionError::Status(502)).is_fatal());
+    }
+
+    #[test]
+    fn exposes_underlying_io_error() {
+        let io = std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out");
+        let error = Error::from(SessionError::from(io));
+
+        assert_eq!(error.kind(), Kind::Network);
+        assert_eq!(std::error::Error::source(&error).unwrap().to_string(), "timed out");
+    }
+}
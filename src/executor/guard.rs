@@ -0,0 +1,84 @@
+use crate::session::{Error, Hotspot, Trait as Session};
+use crate::connections::Trait as Connections;
+
+/// Checks run right before credentials are sent, so they never reach an
+/// access point impersonating the real hotspot. Nothing is checked by
+/// default.
+#[derive(Debug, Clone, Default)]
+pub struct Guard {
+    hotspot: Option<Hotspot>,
+    ouis: Vec<String>
+}
+
+impl Guard {
+
+    pub fn with_hotspot(mut self, hotspot: Hotspot) -> Self {
+        self.hotspot = Some(hotspot);
+        self
+    }
+
+    /// Only trusts access points whose BSSID starts with one of `ouis`,
+    /// e.g. `00:1A:2B`.
+    pub fn with_ouis(mut self, ouis: &[String]) -> Self {
+        self.ouis = ouis.iter().map(|oui| oui.trim().replace('-', ":").to_uppercase()).collect();
+        self
+    }
+
+    fn verify_bssid(&self, connections: &impl Connections, target: &str) -> Result<(), Error> {
+        if self.ouis.is_empty() {
+            return Ok(());
+        }
+        let bssid = connections
+            .bssid(target)
+            .ok_or_else(|| Error::Untrusted("the access point BSSID is unknown".to_string()))?;
+        if self.ouis.iter().any(|oui| bssid.to_uppercase().starts_with(oui.as_str())) {
+            Ok(())
+        } else {
+            Err(Error::Untrusted(format!("access point {} is not from an allowed vendor", bssid)))
+        }
+    }
+
+    pub fn verify(&self, connections: &impl Connections, session: &impl Session, target: &str) -> Result<(), Error> {
+        self.verify_bssid(connections, target)?;
+        match &self.hotspot {
+            Some(hotspot) => hotspot.verify(session.portal().as_ref()),
+            None => Ok(())
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connections::Fake as FakeConnections;
+    use crate::session::Fake as FakeSession;
+    use crate::testing::Journal;
+
+    const TARGET: &str = "MEO-WiFi";
+
+    #[test]
+    fn accepts_access_point_from_allowed_vendor() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        connections.set_bssid(Some("00:1a:2b:3c:4d:5e"));
+        let guard = Guard::default().with_ouis(&["00-1A-2B".to_string()]);
+
+        assert_eq!(guard.verify(&connections, &FakeSession::new(&journal), TARGET), Ok(()));
+    }
+
+    #[test]
+    fn rejects_unknown_vendor_and_missing_bssid() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal);
+        let guard = Guard::default().with_ouis(&["00:1A:2B".to_string()]);
+
+        assert!(matches!(guard.verify(&connections, &session, TARGET), Err(Error::Untrusted(_))));
+        connections.set_bssid(Some("66:77:88:99:AA:BB"));
+        assert_eq!(
+            guard.verify(&connections, &session, TARGET),
+            Err(Error::Untrusted("access point 66:77:88:99:AA:BB is not from an allowed vendor".to_string()))
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use super::clock::{self, Trait as Clock};
+use crate::log;
+use crate::session::Error;
+
+/// Login attempts recorded for an account.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct State {
+    mismatches: u32,
+    invalid_ips: u32,
+    retry_after: u64
+}
+
+impl State {
+
+    fn parse(text: &str) -> Self {
+        let mut state = Self::default();
+        for (key, value) in text.lines().filter_map(|line| line.split_once('=')) {
+            let value = value.trim().parse().unwrap_or_default();
+            match key.trim() {
+                "mismatches" => state.mismatches = value as u32,
+                "invalid_ips" => state.invalid_ips = value as u32,
+                "retry_after" => state.retry_after = value,
+                _ => {}
+            }
+        }
+        state
+    }
+
+    fn serialize(&self) -> String {
+        format!(
+            "mismatches = {}\ninvalid_ips = {}\nretry_after = {}\n",
+            self.mismatches, self.invalid_ips, self.retry_after
+        )
+    }
+
+}
+
+/// Keeps track of rejected logins so a wrong password or a misbehaving
+/// portal doesn't get the account locked by MEO. Logins stop altogether
+/// after repeated credential mismatches and back off exponentially on
+/// invalid IP errors. The state is saved per account, so it survives
+/// restarts.
+pub struct Limiter {
+    path: Option<PathBuf>,
+    clock: Box<dyn Clock>,
+    state: RefCell<State>,
+    max_mismatches: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Self {
+            path: None,
+            clock: Box::new(clock::System),
+            state: RefCell::new(State::default()),
+            max_mismatches: 3,
+            initial_backoff: Duration::from_secs(30),
+            max_backoff: Duration::from_secs(900)
+        }
+    }
+}
+
+impl Limiter {
+
+    /// Persists the attempts of `account` in a file under `dir`, loading
+    /// what previous runs recorded. The file is named after a hash of the
+    /// account, so any name maps to its own file within `dir`.
+    pub fn with_account(mut self, dir: &Path, account: &str) -> Self {
+        let digest = ring::digest::digest(&ring::digest::SHA256, account.as_bytes());
+        let name = digest.as_ref().iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+        let path = dir.join(name);
+        self.state = RefCell::new(std::fs::read_to_string(&path).map(|text| State::parse(&text)).unwrap_or_default());
+        self.path = Some(path);
+        self
+    }
+
+    pub fn with_max_mismatches(mut self, max: u32) -> Self {
+        self.max_mismatches = max.max(1);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    fn now(&self) -> u64 {
+        self.clock.system_time().duration_since(UNIX_EPOCH).map(|now| now.as_secs()).unwrap_or_default()
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(path, self.state.borrow().serialize()));
+        if let Err(error) = result {
+            log::warn(&format!("Failed to save login attempts to {}: {}", path.display(), error));
+        }
+    }
+
+    /// Whether a login may be attempted now.
+    pub fn check(&self) -> Result<(), Error> {
+        let state = *self.state.borrow();
+        if state.mismatches >= self.max_mismatches {
+            return Err(Error::Locked(state.mismatches));
+        }
+        match state.retry_after.checked_sub(self.now()) {
+            Some(wait) if wait > 0 => Err(Error::Throttled(wait)),
+            _ => Ok(())
+        }
+    }
+
+    /// Records the outcome of a login attempt.
+    pub fn record(&self, result: &Result<(), Error>) {
+        {
+            let mut state = self.state.borrow_mut();
+            match result {
+                Ok(()) => *state = State::default(),
+                Err(Error::CredentialsMismatch) => state.mismatches += 1,
+                Err(Error::InvalidIp) => {
+                    let backoff = self.initial_backoff
+                        .saturating_mul(2u32.saturating_pow(state.invalid_ips))
+                        .min(self.max_backoff);
+                    state.invalid_ips += 1;
+                    state.retry_after = self.now() + backoff.as_secs();
+                }
+                Err(_) => return
+            }
+        }
+        self.save();
+    }
+
+    /// Forgets all recorded attempts, e.g. once the credentials are fixed.
+    pub fn reset(&self) {
+        *self.state.borrow_mut() = State::default();
+        self.save();
+    }
+
+}
+
+impl std::fmt::Debug for Limiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Limiter")
+            .field("path", &self.path)
+            .field("state", &self.state)
+            .field("max_mismatches", &self.max_mismatches)
+            .field("initial_backoff", &self.initial_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .finish_non_exhaustive()
+    }
+}
+
+impl std::fmt::Display for Limiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.state.borrow();
+        match self.check() {
+            Err(Error::Locked(mismatches)) => write!(f, "locked after {} rejected logins", mismatches),
+            Err(Error::Throttled(wait)) => write!(f, "backing off for {}s after {} invalid IP errors", wait, state.invalid_ips),
+            _ if state.mismatches > 0 => write!(f, "ok, {} of {} rejected logins before locking", state.mismatches, self.max_mismatches),
+            _ => write!(f, "ok")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::clock::Virtual;
+    use crate::testing::Journal;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("meo-wifi-limiter-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn locks_after_repeated_mismatches_across_restarts() {
+        let dir = dir("lock");
+        let limiter = Limiter::default().with_account(&dir, "user@meo.pt").with_max_mismatches(2);
+        limiter.record(&Err(Error::CredentialsMismatch));
+        assert_eq!(limiter.check(), Ok(()));
+
+        let limiter = Limiter::default().with_account(&dir, "user@meo.pt").with_max_mismatches(2);
+        limiter.record(&Err(Error::CredentialsMismatch));
+
+        assert_eq!(limiter.check(), Err(Error::Locked(2)));
+        assert_eq!(limiter.to_string(), "locked after 2 rejected logins");
+        let other = Limiter::default().with_account(&dir, "other@meo.pt").with_max_mismatches(2);
+        assert_eq!(other.check(), Ok(()));
+    }
+
+    #[test]
+    fn backs_off_exponentially_on_invalid_ip() {
+        let clock = Virtual::new(&Journal::new());
+        let limiter = Limiter::default()
+            .with_clock(clock.clone())
+            .with_backoff(Duration::from_secs(10), Duration::from_secs(25));
+
+        limiter.record(&Err(Error::InvalidIp));
+        assert_eq!(limiter.check(), Err(Error::Throttled(10)));
+        clock.sleep(Duration::from_secs(4));
+        assert_eq!(limiter.check(), Err(Error::Throttled(6)));
+        clock.sleep(Duration::from_secs(6));
+        assert_eq!(limiter.check(), Ok(()));
+
+        limiter.record(&Err(Error::InvalidIp));
+        limiter.record(&Err(Error::InvalidIp));
+        assert_eq!(limiter.check(), Err(Error::Throttled(25)));
+    }
+
+    #[test]
+    fn keeps_every_account_in_its_own_file_within_dir() {
+        let dir = dir("names");
+        for account in ["..", "a/b", "a_b"] {
+            Limiter::default().with_account(&dir, account).record(&Err(Error::CredentialsMismatch));
+        }
+
+        let files = std::fs::read_dir(&dir).unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(files.len(), 3);
+        assert!(files.iter().all(|file| file.file_type().unwrap().is_file()));
+    }
+
+    #[test]
+    fn successful_login_and_reset_clear_attempts() {
+        let dir = dir("reset");
+        let limiter = Limiter::default().with_account(&dir, "user@meo.pt").with_max_mismatches(1);
+        limiter.record(&Err(Error::CredentialsMismatch));
+        limiter.reset();
+        assert_eq!(Limiter::default().with_account(&dir, "user@meo.pt").check(), Ok(()));
+
+        limiter.record(&Err(Error::InvalidIp));
+        limiter.record(&Ok(()));
+        assert_eq!(limiter.check(), Ok(()));
+        assert_eq!(limiter.to_string(), "ok");
+    }
+}
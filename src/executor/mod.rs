@@ -1,10 +1,18 @@
 mod continuous;
 mod oneshot;
 mod error;
+mod guard;
+mod limiter;
+pub (super) mod clock;
+pub (super) mod connectivity;
+pub (super) mod retry;
+pub (super) mod notify;
 
 pub (super) use continuous::Continuous;
 pub (super) use oneshot::Oneshot;
 pub (super) use error::Error;
+pub (super) use guard::Guard;
+pub (super) use limiter::Limiter;
 
 pub (super) trait Trait {
     fn execute(&self) -> Result<(), error::Error>;
@@ -0,0 +1,109 @@
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Reports the executor state to a service manager.
+pub (crate) trait Trait {
+    /// Sends newline separated `KEY=value` assignments, as in `sd_notify(3)`.
+    fn notify(&self, state: &str);
+    /// How often the service manager expects `WATCHDOG=1`, if at all.
+    fn watchdog(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl<F: Fn(&str)> Trait for F {
+    fn notify(&self, state: &str) {
+        self(state)
+    }
+}
+
+/// Reports nothing, for runs outside a service manager.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Silent;
+
+impl Trait for Silent {
+    fn notify(&self, _state: &str) {}
+}
+
+/// Speaks the systemd notification protocol over the datagram socket the
+/// service manager passes in `$NOTIFY_SOCKET`.
+#[derive(Debug)]
+pub struct Systemd {
+    socket: UnixDatagram,
+    address: String,
+    watchdog: Option<Duration>
+}
+
+impl Systemd {
+
+    /// Connects to `$NOTIFY_SOCKET`, if set, picking up the watchdog
+    /// interval from `$WATCHDOG_USEC` when it is meant for this process.
+    pub fn from_env() -> Option<Self> {
+        let address = std::env::var("NOTIFY_SOCKET").ok().filter(|address| !address.is_empty())?;
+        let for_us = std::env::var("WATCHDOG_PID")
+            .map_or(true, |pid| pid.parse() == Ok(std::process::id()));
+        let watchdog = std::env::var("WATCHDOG_USEC")
+            .ok()
+            .filter(|_| for_us)
+            .and_then(|usec| usec.parse().ok())
+            .filter(|usec| *usec > 0)
+            .map(Duration::from_micros);
+        Self::new(&address, watchdog).ok()
+    }
+
+    /// `address` is a socket path, or an abstract socket name after `@`.
+    pub fn new(address: &str, watchdog: Option<Duration>) -> std::io::Result<Self> {
+        Ok(Self {
+            socket: UnixDatagram::unbound()?,
+            address: address.to_string(),
+            watchdog
+        })
+    }
+
+    fn send(&self, state: &str) -> std::io::Result<usize> {
+        match self.address.strip_prefix('@') {
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                self.socket.send_to_addr(state.as_bytes(), &address)
+            }
+            None => self.socket.send_to(state.as_bytes(), PathBuf::from(&self.address))
+        }
+    }
+
+}
+
+impl Trait for Systemd {
+
+    fn notify(&self, state: &str) {
+        if let Err(error) = self.send(state) {
+            crate::log::warn(&format!("Failed to notify systemd: {}", error));
+        }
+    }
+
+    fn watchdog(&self) -> Option<Duration> {
+        self.watchdog
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sends_state_to_notify_socket() {
+        let path = std::env::temp_dir().join(format!("meo-wifi-notify-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        let systemd = Systemd::new(path.to_str().unwrap(), None).unwrap();
+
+        systemd.notify("READY=1\nSTATUS=Online");
+
+        let mut buffer = [0u8; 64];
+        let length = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..length], b"READY=1\nSTATUS=Online");
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,12 +1,17 @@
+use std::cell::Cell;
 use crate::log;
-use crate::session::Trait as Session;
+use crate::session::{Error as SessionError, Trait as Session};
 use crate::connections::Trait as Connections;
-use super::{Error, Trait};
+use super::{Error, Guard, Limiter, Trait};
 
 pub struct Oneshot<'a, C: Connections, S: Session> {
     connections: &'a C,
     session: &'a S,
     target: &'a str,
+    guard: Guard,
+    limiter: Option<Limiter>,
+    manage_connection: bool,
+    logins: Cell<u64>,
 }
 
 impl<'a, C, S> Oneshot<'a, C, S>
@@ -19,40 +24,100 @@ where
             connections,
             session,
             target,
+            guard: Guard::default(),
+            limiter: None,
+            manage_connection: true,
+            logins: Cell::new(0),
         }
     }
 
-    fn setup_connection(&self) -> Result<(), Error> {
+    pub fn with_guard(mut self, guard: Guard) -> Self {
+        self.guard = guard;
+        self
+    }
+
+    /// Limits login attempts. Not needed when the session limits each of
+    /// its accounts itself, as a pool does.
+    #[allow(dead_code)]
+    pub fn with_limiter(mut self, limiter: Limiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Whether to bring the target up and drop other connections first.
+    /// Off when NetworkManager already manages the connection and only the
+    /// portal login is wanted.
+    pub fn with_manage_connection(mut self, manage_connection: bool) -> Self {
+        self.manage_connection = manage_connection;
+        self
+    }
+
+    pub (super) fn limiter(&self) -> Option<&Limiter> {
+        self.limiter.as_ref()
+    }
+
+    /// How many logins succeeded so far, so callers can tell whether a run
+    /// started a new session.
+    pub (super) fn logins(&self) -> u64 {
+        self.logins.get()
+    }
+
+    /// Logs in once the hotspot passed the guard and the limiter allows
+    /// another attempt.
+    pub (super) fn login(&self, target: &str) -> Result<(), SessionError> {
+        self.guard
+            .verify(self.connections, self.session, target)
+            .inspect_err(|error| log::warn(&error.to_string()))?;
+        if let Some(limiter) = &self.limiter {
+            limiter.check()?;
+        }
+        let result = self.session.reconcile_login();
+        if let Some(limiter) = &self.limiter {
+            limiter.record(&result);
+        }
+        if result.is_ok() {
+            self.logins.set(self.logins.get() + 1);
+        }
+        result
+    }
+
+    fn setup_connection(&self, target: &str) -> Result<(), Error> {
         if let Some(main_connection) = self.connections.active() {
-            if main_connection != self.target {
+            if main_connection != target {
                 log::warn("Disconnecting non-target main connection");
                 self.connections.disconnect(&main_connection)?;
                 log::info("Main connection disconnected");
             }
         }
 
-        if self.connections.is_connected(self.target) {
+        if self.connections.is_connected(target) {
             log::info("Target connection already active");
         } else {
             log::warn("Target connection not active, connecting");
-            self.connections.connect(self.target)?;
+            self.connections.connect(target)?;
             log::info("Target connection activated");
         }
 
         Ok(())
     }
 
-    fn setup_session(&self) -> Result<(), Error> {
+    fn setup_session(&self, target: &str) -> Result<(), Error> {
         if self.session.is_logged() {
             log::info("Session already active");
         } else {
             log::warn("Session not active, logging in");
-            self.session.login()?;
+            self.login(target)?;
             log::info("Session logged in successfully");
         }
 
         Ok(())
     }
+
+    /// Brings `target` up and logs in on it, which may differ from the
+    /// target the executor was created with.
+    pub (super) fn run(&self, target: &str) -> Result<(), Error> {
+        self.setup_connection(target).and_then(|_| self.setup_session(target))
+    }
 }
 
 impl<C, S> Trait for Oneshot<'_, C, S>
@@ -61,6 +126,127 @@ where
     S: Session,
 {
     fn execute(&self) -> Result<(), Error> {
-        self.setup_connection().and_then(|_| self.setup_session())
+        if self.manage_connection {
+            self.run(self.target)
+        } else {
+            self.setup_session(self.target)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connections::{Error as ConnectionError, Fake as FakeConnections};
+    use crate::session::{Error as SessionError, Fake as FakeSession};
+    use crate::testing::{Event, Journal};
+
+    const TARGET: &str = "MEO-WiFi";
+
+    #[test]
+    fn disconnects_other_connection_before_connecting_target() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal)
+            .with_profile("Home", true)
+            .with_profile(TARGET, false);
+        let session = FakeSession::new(&journal);
+
+        Oneshot::new(&connections, &session, TARGET).execute().unwrap();
+
+        assert_eq!(journal.events(), vec![
+            Event::Disconnect("Home".into()),
+            Event::Connect(TARGET.into()),
+            Event::Login,
+        ]);
+    }
+
+    #[test]
+    fn does_nothing_when_already_connected_and_logged() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal).logged(true);
+
+        Oneshot::new(&connections, &session, TARGET).execute().unwrap();
+
+        assert!(journal.events().is_empty());
+    }
+
+    #[test]
+    fn only_logs_in_when_connection_is_left_alone() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal)
+            .with_profile("Home", true)
+            .with_profile(TARGET, false);
+        let session = FakeSession::new(&journal);
+
+        Oneshot::new(&connections, &session, TARGET).with_manage_connection(false).execute().unwrap();
+
+        assert_eq!(journal.events(), vec![Event::Login]);
+    }
+
+    #[test]
+    fn skips_login_when_connection_fails() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, false);
+        connections.fail_connect(TARGET, ConnectionError::Unavailable);
+        let session = FakeSession::new(&journal);
+
+        let result = Oneshot::new(&connections, &session, TARGET).execute();
+
+        assert_eq!(result, Err(Error::Connection(ConnectionError::Unavailable)));
+        assert!(journal.events().is_empty());
+    }
+
+    #[test]
+    fn propagates_login_failure() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal);
+        session.fail_login(SessionError::CredentialsMismatch);
+
+        let result = Oneshot::new(&connections, &session, TARGET).execute();
+
+        assert_eq!(result, Err(Error::Session(SessionError::CredentialsMismatch)));
+    }
+
+    #[test]
+    fn recovers_when_portal_claims_session_is_already_active() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal);
+        session.fail_login(SessionError::AlreadyLoggedIn);
+
+        let result = Oneshot::new(&connections, &session, TARGET).execute();
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(journal.events(), vec![Event::Login, Event::Logout, Event::Login]);
+    }
+
+    #[test]
+    fn refuses_to_log_in_on_untrusted_access_point() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        connections.set_bssid(Some("66:77:88:99:AA:BB"));
+        let session = FakeSession::new(&journal);
+        let guard = Guard::default().with_ouis(&["00:1A:2B".to_string()]);
+
+        let result = Oneshot::new(&connections, &session, TARGET).with_guard(guard).execute();
+
+        assert!(matches!(result, Err(Error::Session(SessionError::Untrusted(_)))));
+        assert!(journal.events().is_empty());
+    }
+
+    #[test]
+    fn stops_logging_in_once_limiter_locks_the_account() {
+        let journal = Journal::new();
+        let connections = FakeConnections::new(&journal).with_profile(TARGET, true);
+        let session = FakeSession::new(&journal);
+        session.fail_login(SessionError::CredentialsMismatch);
+        let oneshot = Oneshot::new(&connections, &session, TARGET)
+            .with_limiter(Limiter::default().with_max_mismatches(1));
+
+        assert_eq!(oneshot.execute(), Err(Error::Session(SessionError::CredentialsMismatch)));
+        assert_eq!(oneshot.execute(), Err(Error::Session(SessionError::Locked(1))));
+        assert_eq!(journal.events(), vec![Event::Login]);
     }
 }
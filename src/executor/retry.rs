@@ -0,0 +1,128 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// Exponential backoff between failed connectivity checks, bounded by a
+/// number of consecutive failures and by the time spent failing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Policy {
+    initial_delay: Duration,
+    max_delay: Duration,
+    max_attempts: u32,
+    max_duration: Duration,
+    jitter: f64
+}
+
+impl Policy {
+
+    pub fn new() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(5),
+            max_delay: Duration::from_secs(300),
+            max_attempts: 10,
+            max_duration: Duration::from_secs(1800),
+            jitter: 0.2
+        }
+    }
+
+    /// Gives up on the first failure.
+    #[allow(dead_code)]
+    pub fn disabled() -> Self {
+        Self::new().with_max_attempts(1)
+    }
+
+    #[allow(dead_code)]
+    pub fn with_delays(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_delay = initial;
+        self.max_delay = max.max(initial);
+        self
+    }
+
+    pub fn with_max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_max_duration(mut self, duration: Duration) -> Self {
+        self.max_duration = duration;
+        self
+    }
+
+    /// Fraction of each delay, between 0 and 1, that may be shaved off at
+    /// random so that several clients don't retry in lockstep.
+    #[allow(dead_code)]
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn is_exhausted(&self, failures: u32, failing_for: Duration) -> bool {
+        failures >= self.max_attempts || failing_for >= self.max_duration
+    }
+
+    /// Delay to wait after the given number of consecutive failures.
+    pub fn delay(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(1).min(31);
+        let delay = self.initial_delay
+            .checked_mul(1 << exponent)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        delay.mul_f64(1.0 - self.jitter * Self::random(failures))
+    }
+
+    /// Uniform value in `[0, 1)`. Every `RandomState` is keyed afresh from
+    /// a per-process random seed, so draws don't follow the clock and
+    /// differ between machines woken up by the same event.
+    fn random(failures: u32) -> f64 {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(failures);
+        (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubles_delay_up_to_maximum() {
+        let policy = Policy::new()
+            .with_delays(Duration::from_secs(5), Duration::from_secs(30))
+            .with_jitter(0.0);
+
+        let delays: Vec<_> = (1..=5).map(|n| policy.delay(n).as_secs()).collect();
+
+        assert_eq!(delays, vec![5, 10, 20, 30, 30]);
+    }
+
+    #[test]
+    fn jitter_only_shortens_delay() {
+        let policy = Policy::new()
+            .with_delays(Duration::from_secs(10), Duration::from_secs(10))
+            .with_jitter(0.5);
+
+        let delays = (0..100).map(|_| policy.delay(1)).collect::<Vec<_>>();
+
+        assert!(delays.iter().all(|delay| *delay <= Duration::from_secs(10) && *delay >= Duration::from_secs(5)));
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+    }
+
+    #[test]
+    fn exhausts_on_attempts_or_duration() {
+        let policy = Policy::new()
+            .with_max_attempts(3)
+            .with_max_duration(Duration::from_secs(60));
+
+        assert!(!policy.is_exhausted(2, Duration::from_secs(59)));
+        assert!(policy.is_exhausted(3, Duration::ZERO));
+        assert!(policy.is_exhausted(1, Duration::from_secs(60)));
+    }
+}
@@ -0,0 +1,107 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{ErrorKind, Read, Seek, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use crate::error::{Kind, Source};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    Running(Option<u32>),
+    Io(PathBuf, Source)
+}
+
+impl Error {
+
+    pub fn kind(&self) -> Kind {
+        Kind::Internal
+    }
+
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(_, e) => Some(e.inner()),
+            _ => None
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Running(Some(pid)) => write!(f, "Another instance is already running (pid {})", pid),
+            Error::Running(None) => write!(f, "Another instance is already running"),
+            Error::Io(path, e) => write!(f, "Failed to lock {}: {}", path.display(), e),
+        }
+    }
+}
+
+/// Lock file shared by every instance on the system, whichever user or
+/// mode runs it, so a manual run sees the service.
+pub const DEFAULT_PATH: &str = "/run/lock/meo-wifi.pid";
+
+/// Exclusive lock on a PID file, held until dropped, so only one executor
+/// drives NetworkManager at a time. The file itself is left behind: only
+/// the lock tells whether an instance is running. A file another user
+/// created can still be locked, but not written the PID to.
+#[derive(Debug)]
+pub struct Lock {
+    _file: File
+}
+
+impl Lock {
+
+    pub fn acquire(path: &Path) -> Result<Self, Error> {
+        let io = |e: std::io::Error| Error::Io(path.to_path_buf(), Source::new(e));
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).map_err(io)?;
+        }
+        let (mut file, writable) = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o644)
+            .open(path)
+        {
+            Ok(file) => (file, true),
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => (File::open(path).map_err(io)?, false),
+            Err(e) => return Err(io(e))
+        };
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut pid = String::new();
+                let _ = file.read_to_string(&mut pid);
+                return Err(Error::Running(pid.trim().parse().ok()));
+            }
+            Err(TryLockError::Error(e)) => return Err(io(e))
+        }
+        if !writable {
+            return Ok(Self { _file: file });
+        }
+        file.set_len(0).map_err(io)?;
+        file.rewind().map_err(io)?;
+        writeln!(file, "{}", std::process::id()).map_err(io)?;
+        Ok(Self { _file: file })
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_instance_sees_pid_of_the_first_until_released() {
+        let path = std::env::temp_dir().join(format!("meo-wifi-lock-{}.pid", std::process::id()));
+        let lock = Lock::acquire(&path).unwrap();
+
+        assert_eq!(Lock::acquire(&path).unwrap_err(), Error::Running(Some(std::process::id())));
+
+        drop(lock);
+        assert!(Lock::acquire(&path).is_ok());
+        let _ = std::fs::remove_file(&path);
+    }
+}
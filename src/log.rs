@@ -1,7 +1,7 @@
 use std::time::Instant;
 use std::sync::LazyLock;
 
-static INSTANT: LazyLock<Instant> = LazyLock::new(|| Instant::now());
+static INSTANT: LazyLock<Instant> = LazyLock::new(Instant::now);
 
 fn print(subject: &str, message: &str) {
     let elapsed = INSTANT.elapsed();
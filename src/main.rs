@@ -1,12 +1,255 @@
+mod error;
+mod config;
 mod connections;
 mod session;
 mod log;
 mod args;
 mod executor;
+mod control;
+mod lock;
+mod systemd;
+mod dispatcher;
+#[cfg(test)]
+mod testing;
+
+fn execute<S: session::Trait>(
+    args: &args::Args,
+    session: &S,
+    guard: &executor::Guard,
+    transport: &session::Transport,
+    control: Option<&control::Control>
+) -> Result<(), executor::Error> {
+    let network_manager = connections::Nmcli::new();
+
+    let executor: Box<dyn executor::Trait> = if args.is_one_shot() || args.dispatcher().is_some() {
+        Box::new(executor::Oneshot::new(&network_manager, session, args.target())
+            .with_manage_connection(args.dispatcher().is_none())
+            .with_guard(guard.clone()))
+    } else {
+        let captive_api = match args.captive_api() {
+            args::CaptiveApi::Dhcp => Some(session::captive::Discovery::Dhcp),
+            args::CaptiveApi::Url(url) => Some(session::captive::Discovery::Url(url.clone())),
+            args::CaptiveApi::Off => None,
+        };
+        let continuous = executor::Continuous::new(&network_manager, session, args.target())
+            .with_captive_api(captive_api)
+            .with_transport(transport)
+            .with_max_session(args.max_session())
+            .with_relogin_margin(args.relogin_margin())
+            .with_guard(guard.clone());
+        let continuous = match control {
+            Some(control) => continuous.with_control(control.clone()),
+            None => continuous
+        };
+        let continuous = match executor::notify::Systemd::from_env() {
+            Some(systemd) => continuous.with_notifier(systemd),
+            None => continuous
+        };
+        Box::new(match args.probe() {
+            args::Probe::Tcp => continuous.with_connectivity(executor::connectivity::Tcp::new()),
+            args::Probe::Http => continuous.with_connectivity(executor::connectivity::Http::new()),
+            args::Probe::Nm => continuous.with_connectivity(
+                executor::connectivity::Network::new(&network_manager).with_fallback(executor::connectivity::Http::new())
+            ),
+        })
+    };
+
+    executor.execute()
+}
+
+/// Login attempt limiter of `account`, saved under `state_dir` if any.
+fn limiter(args: &args::Args, state_dir: Option<&std::path::Path>, account: &str) -> executor::Limiter {
+    let limiter = executor::Limiter::default().with_max_mismatches(args.max_login_failures());
+    match state_dir {
+        Some(dir) => limiter.with_account(&dir.join("logins"), account),
+        None => limiter
+    }
+}
+
+/// Builds a session for each account, tried in order by the pool, each
+/// with its own login attempt limiter.
+fn pool<S: session::Trait>(
+    args: &args::Args,
+    accounts: &[(String, String)],
+    state_dir: Option<&std::path::Path>,
+    build: impl Fn(&str, &str) -> Result<S, session::Error>
+) -> Result<session::Pool<S>, session::Error> {
+    let sessions = accounts
+        .iter()
+        .map(|(username, password)| Ok((username.clone(), build(username, password)?)))
+        .collect::<Result<Vec<_>, session::Error>>()?;
+    let pool = session::Pool::new(sessions).with_limiters(|account| limiter(args, state_dir, account));
+    Ok(match state_dir {
+        Some(dir) => pool.with_state_file(&dir.join("active-account")),
+        None => pool
+    })
+}
+
+/// Sets everything up from the arguments and the config file and runs the
+/// executor. In daemon mode it returns once asked to reload the config.
+fn start(args: &args::Args, control: Option<&control::Control>) -> Result<(), Box<dyn std::error::Error>> {
+    let config = match args.config() {
+        Some(path) => config::Config::load(std::path::Path::new(path), true)?,
+        None => match config::Config::default_path() {
+            Some(path) => config::Config::load(&path, false)?,
+            None => config::Config::default()
+        }
+    };
+
+    let accounts = args
+        .credentials()
+        .map(|(username, password)| (username.to_string(), password.to_string()))
+        .or_else(systemd::credentials)
+        .into_iter()
+        .chain(config.accounts().iter().cloned())
+        .collect::<Vec<_>>();
+    if accounts.is_empty() {
+        return Err(error::Usage("Missing username and password".to_string()).into());
+    }
+    let state_dir = config::Config::state_dir();
+
+    for (account, _) in &accounts {
+        let limiter = limiter(args, state_dir.as_deref(), account);
+        if args.reset_login_limit() {
+            limiter.reset();
+        }
+        if args.is_status() {
+            println!("Login attempts for {}: {}", account, limiter);
+        }
+    }
+    if args.is_status() {
+        return Ok(());
+    }
+
+    let validation = match args.validation() {
+        args::Validation::Strict => session::Validation::Strict,
+        args::Validation::Warn => session::Validation::Warn,
+        args::Validation::Off => session::Validation::Off,
+    };
+
+    let list = |config: Option<&str>, args: &[String]| -> Vec<String> {
+        config
+            .into_iter()
+            .flat_map(|values| values.split(','))
+            .chain(args.iter().map(String::as_str))
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+    let mut portal_domains = list(config.portal_domains(), args.portal_domains());
+    let meo_portal = args.portal_file().is_none()
+        && matches!(args.portal(), args::Portal::Legacy | args::Portal::Modern | args::Portal::Auto);
+    if portal_domains.is_empty() && meo_portal {
+        portal_domains.push("meo.pt".to_string());
+    }
+
+    // The environment's proxy is honoured unless turned off with `none`.
+    // Portal hosts never go through a proxy, as it is usually unreachable
+    // before logging in.
+    let mut proxy = match args.proxy().or(config.proxy()) {
+        Some("none") => session::Proxy::default(),
+        Some("env") | None => session::Proxy::from_env(),
+        Some(url) => session::Proxy::default().with_url(url)?
+    };
+    if let Some(hosts) = config.no_proxy() {
+        proxy = proxy.with_bypass(hosts);
+    }
+    proxy = proxy.with_bypass(&portal_domains.join(","));
+
+    let mut resolver = session::Resolver::default();
+    if let Some(dns) = args.dns().or(config.dns()) {
+        resolver = resolver.with_nameserver(dns.parse::<session::Nameserver>().map_err(error::Usage)?);
+    }
+    for hosts in config.hosts().into_iter().chain(args.hosts().iter().map(String::as_str)) {
+        resolver = resolver.with_hosts(hosts).map_err(error::Usage)?;
+    }
+    let mut transport = session::Transport::default().with_proxy(proxy).with_resolver(resolver);
+    if let Some(path) = args.trust_store().or(config.trust_store()) {
+        transport = transport.with_trust_store(std::path::Path::new(path))?;
+    }
+
+    let pins = config
+        .pins()
+        .into_iter()
+        .flat_map(|pins| pins.split(','))
+        .chain(args.pins().iter().map(String::as_str))
+        .map(str::parse)
+        .collect::<Result<Vec<session::Pin>, _>>()?;
+
+    let mut guard = executor::Guard::default().with_ouis(&list(config.allowed_ouis(), args.allowed_ouis()));
+    // Without portal domains there is nothing to check the hotspot against,
+    // so the check only runs by default when some are known.
+    if args.hotspot_check().unwrap_or(!portal_domains.is_empty()) {
+        if portal_domains.is_empty() {
+            return Err(error::Usage("The hotspot check needs at least one portal domain".to_string()).into());
+        }
+        guard = guard.with_hotspot(session::Hotspot::new(&portal_domains).with_transport(&transport));
+    }
+
+    let legacy = |username: &str, password: &str| -> Result<session::Legacy, session::Error> {
+        let legacy = session::Legacy::new(username, password, validation)?
+            .with_transport(&transport)
+            .with_pins(&pins);
+        let legacy = match args.timeout() {
+            Some(timeout) => legacy.with_timeout(timeout),
+            None => legacy
+        };
+        let legacy = match args.user_agent() {
+            Some(user_agent) => legacy.with_user_agent(user_agent),
+            None => legacy
+        };
+        match args.cookie_file() {
+            Some(path) => legacy.with_cookie_file(path),
+            None => Ok(legacy)
+        }
+    };
+    let modern = |username: &str, password: &str| {
+        session::Modern::new(username, password, validation).map(|s| s.with_transport(&transport))
+    };
+    let state_dir = state_dir.as_deref();
+
+    if let Some(file) = args.portal_file() {
+        let path = std::path::Path::new(file);
+        let scripted = pool(args, &accounts, state_dir, |username, password| {
+            session::Scripted::from_file(username, password, validation, path).map(|s| s.with_transport(&transport))
+        })?;
+        execute(args, &scripted, &guard, &transport, control)?;
+        return Ok(());
+    }
+
+    match args.portal() {
+        args::Portal::Legacy => execute(args, &pool(args, &accounts, state_dir, legacy)?, &guard, &transport, control)?,
+        args::Portal::Modern => execute(args, &pool(args, &accounts, state_dir, modern)?, &guard, &transport, control)?,
+        args::Portal::Auto => {
+            let auto = pool(args, &accounts, state_dir, |username, password| {
+                Ok(session::Fallback::new(modern(username, password)?, legacy(username, password)?))
+            })?;
+            execute(args, &auto, &guard, &transport, control)?
+        }
+        args::Portal::Fon => {
+            let fon = pool(args, &accounts, state_dir, |username, password| {
+                session::Fon::new(username, password, validation).map(|s| s.with_transport(&transport))
+            })?;
+            execute(args, &fon, &guard, &transport, control)?
+        }
+        args::Portal::Wispr => {
+            let wispr = pool(args, &accounts, state_dir, |username, password| {
+                let wispr = session::Wispr::new(username, password, validation)?.with_transport(&transport);
+                // Polls for a pending login may outlast the systemd watchdog.
+                Ok(match executor::notify::Systemd::from_env().and_then(executor::clock::Watchdog::new) {
+                    Some(watchdog) => wispr.with_clock(watchdog),
+                    None => wispr
+                })
+            })?;
+            execute(args, &wispr, &guard, &transport, control)?
+        }
+    }
+    Ok(())
+}
 
 fn run() -> Result<(), Box<dyn std::error::Error>> {
-    const CONNECTION_NAME: &str = "MEO-WiFi";
-    let (args, info) = args::new()?.unwrap();
+    let (args, info) = args::new().map_err(error::Usage)?.unwrap();
 
     if let Some(info) = info {
         println!("{}", info);
@@ -15,22 +258,118 @@ fn run() -> Result<(), Box<dyn std::error::Error>> {
 
     let args = args.unwrap();
 
-    let network_manager = connections::Nmcli::new();
-    let session = session::Legacy::new(args.user(), args.pass())?;
+    if args.is_systemd_unit() {
+        let exe = std::env::current_exe()?;
+        print!("{}", systemd::unit(&exe, &std::env::args().skip(1).collect::<Vec<_>>()));
+        return Ok(());
+    }
+
+    if args.is_install_dispatcher() {
+        // The script runs with settings from /etc, and never with the
+        // credentials given here.
+        let config = std::path::Path::new(args.config().unwrap_or(dispatcher::CONFIG_PATH));
+        if config::Config::load(config, false)?.accounts().is_empty() {
+            return Err(error::Usage(format!(
+                "No account in {}, which the dispatcher script logs in with; add one as account = <username>:<password>",
+                config.display()
+            )).into());
+        }
+        let exe = std::env::current_exe()?;
+        let script = dispatcher::script(&exe, &std::env::args().skip(1).collect::<Vec<_>>());
+        let path = std::path::Path::new(dispatcher::SCRIPT_PATH);
+        dispatcher::install(path, &script)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("Failed to write {}: {}", path.display(), e)))?;
+        log::info(&format!("Installed NetworkManager dispatcher script {}", path.display()));
+        return Ok(());
+    }
+
+    if let Some((interface, action)) = args.dispatcher() {
+        let event = dispatcher::Event::from_env(interface, action);
+        if !event.concerns(args.target(), &connections::Nmcli::new()) {
+            return Ok(());
+        }
+        log::info(&format!("NetworkManager event: {}", event));
+    }
+
+    let socket = || {
+        args.socket()
+            .map(std::path::PathBuf::from)
+            .or_else(control::default_socket)
+            .ok_or_else(|| error::Usage("XDG_RUNTIME_DIR is not set, pass the control socket with --socket".to_string()))
+    };
+
+    if let Some(command) = args.control() {
+        let command = command.parse::<control::Command>().map_err(error::Usage)?;
+        println!("{}", control::request(&socket()?, &command)?);
+        return Ok(());
+    }
 
-    let executor: Box<dyn executor::Trait> = if args.is_one_shot() {
-        Box::new(executor::Oneshot::new(&network_manager, &session, CONNECTION_NAME))
+    // Held until exit; `--status` only reads state, so it may run alongside.
+    let _lock = if args.is_status() {
+        None
     } else {
-        Box::new(executor::Continuous::new(&network_manager, &session, CONNECTION_NAME))
+        let pid_file = std::path::Path::new(args.pid_file().unwrap_or(lock::DEFAULT_PATH));
+        match lock::Lock::acquire(pid_file) {
+            Ok(lock) => Some(lock),
+            Err(error @ lock::Error::Running(_)) if args.forward() => {
+                if !args::only_target(&std::env::args().skip(1).collect::<Vec<_>>()) {
+                    let reason = "only the target can be forwarded, not credentials or other options";
+                    return Err(error::Usage(format!("{}, {}", error, reason)).into());
+                }
+                log::info(&format!("{}, forwarding the request to it", error));
+                let socket = socket()?;
+                for command in [control::Command::SwitchTarget(args.target().to_string()), control::Command::ForceRelogin] {
+                    println!("{}", control::request(&socket, &command)?);
+                }
+                return Ok(());
+            }
+            Err(error @ lock::Error::Running(_)) if args.dispatcher().is_some() => {
+                log::info(&format!("{}, leaving the login to it", error));
+                return Ok(());
+            }
+            Err(error) => return Err(error.into())
+        }
     };
 
-    executor.execute()?;
-    Ok(())
+    if args.is_daemon() {
+        let socket = socket()?;
+        let control = control::Control::default();
+        control::serve(&socket, control.clone())?;
+        log::info(&format!("Listening for commands on {}", socket.display()));
+        loop {
+            start(&args, Some(&control))?;
+            log::info("Reloading configuration");
+        }
+    }
+
+    start(&args, None)
+}
+
+fn exit_code(error: &(dyn std::error::Error + 'static)) -> i32 {
+    const USAGE: i32 = 2;
+    if let Some(error) = error.downcast_ref::<executor::Error>() {
+        error.kind().exit_code()
+    } else if let Some(error) = error.downcast_ref::<session::Error>() {
+        error.kind().exit_code()
+    } else if let Some(error) = error.downcast_ref::<connections::Error>() {
+        error.kind().exit_code()
+    } else if let Some(error) = error.downcast_ref::<lock::Error>() {
+        error.kind().exit_code()
+    } else if let Some(error) = error.downcast_ref::<config::Error>() {
+        match error {
+            config::Error::Io(..) => error::Kind::Internal.exit_code(),
+            _ => USAGE
+        }
+    } else if error.is::<error::Usage>() {
+        USAGE
+    } else {
+        error::Kind::Internal.exit_code()
+    }
 }
 
-fn main() -> () {
+fn main() {
     if let Err(error) = run() {
         eprintln!("Error: {}", error);
-        std::process::exit(1);
+        std::process::exit(exit_code(error.as_ref()));
     }
 }
@@ -0,0 +1,125 @@
+use serde::Deserialize;
+use url::{Host, Url};
+use super::error::Error;
+use super::transport::{self, Transport};
+
+/// Where to find the RFC 8908 Captive Portal API of the hotspot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Discovery {
+    /// Read the URI the network advertises in DHCP option 114 (RFC 8910).
+    Dhcp,
+    /// Use a fixed API URI.
+    Url(String)
+}
+
+/// State reported by the Captive Portal API.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Status {
+    pub captive: bool,
+    #[serde(default)]
+    pub user_portal_url: Option<String>,
+    #[serde(default)]
+    pub seconds_remaining: Option<u64>,
+    #[serde(default)]
+    pub bytes_remaining: Option<u64>
+}
+
+/// Client for an RFC 8908 Captive Portal API.
+#[derive(Debug, Clone)]
+pub struct CaptiveApi {
+    url: Url,
+    agent: transport::Agent
+}
+
+impl CaptiveApi {
+
+    /// URI a network sends to say it has no captive portal at all.
+    const UNRESTRICTED: &str = "urn:ietf:params:capport:unrestricted";
+
+    /// Client for the API at `url`, or `None` when the network has none.
+    /// The API must be served over HTTPS, as RFC 8908 requires, since
+    /// anyone on the hotspot could otherwise claim the session is captive;
+    /// plain HTTP is only accepted on the loopback interface.
+    pub fn new(url: &str) -> Result<Option<Self>, Error> {
+        let url = match url.trim() {
+            "" | Self::UNRESTRICTED => return Ok(None),
+            url => url
+        };
+        let untrusted = || Error::Untrusted(format!("its Captive Portal API {} is not served over HTTPS", url));
+        let url = Url::parse(url).map_err(|_| untrusted())?;
+        let loopback = match url.host() {
+            Some(Host::Ipv4(ip)) => ip.is_loopback(),
+            Some(Host::Ipv6(ip)) => ip.is_loopback(),
+            Some(Host::Domain(domain)) => domain == "localhost",
+            None => false
+        };
+        if url.scheme() != "https" && !(url.scheme() == "http" && loopback) {
+            return Err(untrusted());
+        }
+        Ok(Some(Self { url, agent: Self::agent(&Transport::default()) }))
+    }
+
+    pub fn with_transport(mut self, transport: &Transport) -> Self {
+        self.agent = Self::agent(transport);
+        self
+    }
+
+    fn agent(transport: &Transport) -> transport::Agent {
+        const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+        transport.agent(|| ureq::AgentBuilder::new().timeout(TIMEOUT))
+    }
+
+    pub fn status(&self) -> Result<Status, Error> {
+        let response = self.agent
+            .request_url("GET", &self.url)
+            .set("Accept", "application/captive+json")
+            .call()?;
+        Ok(response.into_json()?)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Response, Server};
+
+    #[test]
+    fn reads_captive_portal_state() {
+        let server = Server::start(|_| Response::ok(r#"{
+            "captive": false,
+            "user-portal-url": "https://portal.example/",
+            "venue-info-url": "https://venue.example/",
+            "seconds-remaining": 326,
+            "bytes-remaining": 65536,
+            "can-extend-session": true
+        }"#).header("Content-Type", "application/captive+json"));
+
+        let status = CaptiveApi::new(&server.url("/capport")).unwrap().unwrap().status();
+
+        assert_eq!(status, Ok(Status {
+            captive: false,
+            user_portal_url: Some("https://portal.example/".to_string()),
+            seconds_remaining: Some(326),
+            bytes_remaining: Some(65536)
+        }));
+        assert_eq!(server.requests()[0].header("Accept"), Some("application/captive+json"));
+    }
+
+    #[test]
+    fn unrestricted_networks_have_no_api() {
+        assert!(matches!(CaptiveApi::new("urn:ietf:params:capport:unrestricted"), Ok(None)));
+        assert!(matches!(CaptiveApi::new(""), Ok(None)));
+    }
+
+    #[test]
+    fn rejects_api_not_served_over_https() {
+        assert!(matches!(CaptiveApi::new("https://capport.example/api"), Ok(Some(_))));
+        assert_eq!(
+            CaptiveApi::new("http://capport.example/api").err(),
+            Some(Error::Untrusted("its Captive Portal API http://capport.example/api is not served over HTTPS".to_string()))
+        );
+        assert!(matches!(CaptiveApi::new("capport.example"), Err(Error::Untrusted(_))));
+    }
+}
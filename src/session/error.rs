@@ -1,44 +1,136 @@
+use crate::error::{Kind, Source};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordRule {
+    MinLength(usize),
+    Lowercase,
+    Uppercase,
+    Digit
+}
+
+impl std::fmt::Display for PasswordRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PasswordRule::MinLength(length) => write!(f, "must be at least {} characters long", length),
+            PasswordRule::Lowercase => write!(f, "must contain a lowercase letter"),
+            PasswordRule::Uppercase => write!(f, "must contain an uppercase letter"),
+            PasswordRule::Digit => write!(f, "must contain a digit"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
     CredentialsMismatch,
     InvalidIp,
-    InvalidEmail,
-    InvalidPassword,
+    InvalidUsername,
+    InvalidPassword(PasswordRule),
     NetworkUnreachable,
     AlreadyLoggedIn,
-    Custom(String)
+    Transport(Source),
+    Dns(Source),
+    Tls(Source),
+    PinMismatch(String),
+    Untrusted(String),
+    Locked(u32),
+    Throttled(u64),
+    Status(u16),
+    Malformed(Source),
+    Portal(String),
+    Encryption(String),
+    Definition(String),
+    Proxy(String),
+    TlsConfig(String)
+}
+
+impl Error {
+
+    pub fn kind(&self) -> Kind {
+        match self {
+            Error::CredentialsMismatch
+            | Error::InvalidUsername
+            | Error::InvalidPassword(_)
+            | Error::Locked(_) => Kind::Auth,
+            Error::InvalidIp
+            | Error::Throttled(_)
+            | Error::NetworkUnreachable
+            | Error::Transport(_)
+            | Error::Dns(_)
+            | Error::Tls(_) => Kind::Network,
+            Error::PinMismatch(_)
+            | Error::Untrusted(_) => Kind::Security,
+            Error::AlreadyLoggedIn
+            | Error::Status(_)
+            | Error::Malformed(_)
+            | Error::Portal(_) => Kind::Protocol,
+            Error::Encryption(_)
+            | Error::Definition(_)
+            | Error::Proxy(_)
+            | Error::TlsConfig(_) => Kind::Internal,
+        }
+    }
+
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Transport(e) | Error::Dns(e) | Error::Tls(e) | Error::Malformed(e) => Some(e.inner()),
+            _ => None
+        }
+    }
+}
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::CredentialsMismatch => write!(f, "Credentials do not match."),
             Error::InvalidIp => write!(f, "Invalid IP address."),
-            Error::InvalidEmail => write!(f, "Invalid email address."),
-            Error::InvalidPassword => write!(f, "Invalid password."),
+            Error::InvalidUsername => write!(f, "Username must be an email address or a phone number."),
+            Error::InvalidPassword(rule) => write!(f, "Password {}.", rule),
             Error::NetworkUnreachable => write!(f, "Network is unreachable."),
             Error::AlreadyLoggedIn => write!(f, "User is already logged in."),
-            Error::Custom(msg) => write!(f, "Custom error: {}", msg),
+            Error::Transport(e) => write!(f, "Portal request failed: {}", e),
+            Error::Dns(e) => write!(f, "Could not resolve portal host: {}", e),
+            Error::Tls(e) => write!(f, "Portal TLS handshake failed: {}", e),
+            Error::Untrusted(reason) => write!(f, "Hotspot looks untrustworthy, {}; refusing to send credentials.", reason),
+            Error::PinMismatch(pin) => write!(f, "Portal certificate key {} is not pinned, refusing to send credentials.", pin),
+            Error::Locked(attempts) => write!(f, "Login locked after {} rejected attempts, fix the credentials and run with --reset-login-limit.", attempts),
+            Error::Throttled(wait) => write!(f, "Portal keeps reporting an invalid IP address, next login attempt in {}s.", wait),
+            Error::Status(code) => write!(f, "Portal answered with HTTP status {}.", code),
+            Error::Malformed(e) => write!(f, "Malformed portal response: {}", e),
+            Error::Portal(msg) => write!(f, "Portal error: {}", msg),
+            Error::Encryption(msg) => write!(f, "Failed to encrypt password: {}", msg),
+            Error::Definition(msg) => write!(f, "Invalid portal definition: {}", msg),
+            Error::Proxy(msg) => write!(f, "Invalid proxy: {}", msg),
+            Error::TlsConfig(msg) => write!(f, "Invalid TLS settings: {}", msg),
         }
     }
 }
 
 impl From<ureq::Error> for Error {
     fn from(e: ureq::Error) -> Self {
-        Self::Custom(e.to_string())
+        match e {
+            ureq::Error::Status(code, _) => Self::Status(code),
+            ureq::Error::Transport(transport) if transport.kind() == ureq::ErrorKind::Dns => {
+                Self::Dns(Source::new(transport))
+            }
+            ureq::Error::Transport(transport) => match super::tls::handshake_error(&transport) {
+                Some(tls) => match super::tls::mismatch(tls) {
+                    Some(pin) => Self::PinMismatch(pin.to_string()),
+                    None => Self::Tls(Source::new(transport))
+                },
+                None => Self::Transport(Source::new(transport))
+            },
+        }
     }
 }
 
 impl From<std::io::Error> for Error {
     fn from(e: std::io::Error) -> Self {
-        Self::Custom(e.to_string())
+        match e.kind() {
+            std::io::ErrorKind::InvalidData => Self::Malformed(Source::new(e)),
+            _ => Self::Transport(Source::new(e)),
+        }
     }
 }
-
-impl From<&str> for Error {
-    fn from(e: &str) -> Self {
-        Self::Custom(e.to_string())
-    }
-}
\ No newline at end of file
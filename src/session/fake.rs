@@ -0,0 +1,76 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use super::error::Error;
+use crate::testing::{Event, Journal};
+
+#[derive(Debug, Default)]
+struct State {
+    logged: bool,
+    login_failures: VecDeque<Error>,
+    logout_failures: VecDeque<Error>
+}
+
+/// In-memory stand-in for a portal session, sharing its state between clones.
+#[derive(Debug, Clone)]
+pub struct Fake {
+    state: Arc<Mutex<State>>,
+    journal: Journal
+}
+
+impl Fake {
+
+    pub fn new(journal: &Journal) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State::default())),
+            journal: journal.clone()
+        }
+    }
+
+    pub fn logged(self, logged: bool) -> Self {
+        self.set_logged(logged);
+        self
+    }
+
+    /// Changes the portal state without going through the journal, as if
+    /// the session expired or was opened elsewhere.
+    pub fn set_logged(&self, logged: bool) {
+        self.state.lock().unwrap().logged = logged;
+    }
+
+    pub fn fail_login(&self, error: Error) {
+        self.state.lock().unwrap().login_failures.push_back(error);
+    }
+
+    pub fn fail_logout(&self, error: Error) {
+        self.state.lock().unwrap().logout_failures.push_back(error);
+    }
+
+}
+
+impl super::Trait for Fake {
+
+    fn is_logged(&self) -> bool {
+        self.state.lock().unwrap().logged
+    }
+
+    fn login(&self) -> Result<(), Error> {
+        self.journal.record(Event::Login);
+        let mut state = self.state.lock().unwrap();
+        if let Some(error) = state.login_failures.pop_front() {
+            return Err(error);
+        }
+        state.logged = true;
+        Ok(())
+    }
+
+    fn logout(&self) -> Result<(), Error> {
+        self.journal.record(Event::Logout);
+        let mut state = self.state.lock().unwrap();
+        if let Some(error) = state.logout_failures.pop_front() {
+            return Err(error);
+        }
+        state.logged = false;
+        Ok(())
+    }
+
+}
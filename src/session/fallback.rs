@@ -0,0 +1,131 @@
+use std::sync::{Arc, Mutex};
+use super::error::Error;
+use crate::error::Kind;
+use crate::log;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    Primary,
+    Secondary
+}
+
+/// Logs in with the primary session, falling back to the secondary one when
+/// the primary portal can't be used. Rejected credentials are not retried,
+/// since the other portal would reject them too, and neither is a login
+/// the primary portal positively reports as active.
+#[derive(Debug, Clone)]
+pub struct Fallback<P: super::Trait, S: super::Trait> {
+    primary: P,
+    secondary: S,
+    active: Arc<Mutex<Option<Backend>>>
+}
+
+impl<P: super::Trait, S: super::Trait> Fallback<P, S> {
+
+    pub fn new(primary: P, secondary: S) -> Self {
+        Self {
+            primary,
+            secondary,
+            active: Arc::new(Mutex::new(None))
+        }
+    }
+
+    fn set_active(&self, backend: Option<Backend>) {
+        *self.active.lock().unwrap() = backend;
+    }
+
+}
+
+impl<P: super::Trait, S: super::Trait> super::Trait for Fallback<P, S> {
+
+    fn is_logged(&self) -> bool {
+        self.primary.is_logged() || self.secondary.is_logged()
+    }
+
+    fn login(&self) -> Result<(), Error> {
+        match self.primary.login() {
+            Ok(()) => {
+                self.set_active(Some(Backend::Primary));
+                Ok(())
+            }
+            Err(error) if error.kind() == Kind::Auth || error == Error::AlreadyLoggedIn => Err(error),
+            Err(error) => {
+                log::warn(&format!("{}, falling back to the other portal", error));
+                self.secondary.login()?;
+                self.set_active(Some(Backend::Secondary));
+                Ok(())
+            }
+        }
+    }
+
+    fn portal(&self) -> Option<url::Url> {
+        self.primary.portal().or_else(|| self.secondary.portal())
+    }
+
+    fn logout(&self) -> Result<(), Error> {
+        let active = *self.active.lock().unwrap();
+        let result = match active {
+            Some(Backend::Primary) => self.primary.logout(),
+            Some(Backend::Secondary) => self.secondary.logout(),
+            None => self.primary.logout().or_else(|_| self.secondary.logout())
+        };
+        if result.is_ok() {
+            self.set_active(None);
+        }
+        result
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Fake, Trait};
+    use crate::testing::Journal;
+
+    #[test]
+    fn uses_primary_when_it_works() {
+        let journal = Journal::new();
+        let (primary, secondary) = (Fake::new(&journal), Fake::new(&journal));
+        let session = Fallback::new(primary.clone(), secondary.clone());
+
+        assert_eq!(session.login(), Ok(()));
+        assert!(primary.is_logged() && !secondary.is_logged());
+    }
+
+    #[test]
+    fn falls_back_and_logs_out_of_secondary() {
+        let journal = Journal::new();
+        let (primary, secondary) = (Fake::new(&journal), Fake::new(&journal));
+        primary.fail_login(Error::Status(404));
+        let session = Fallback::new(primary.clone(), secondary.clone());
+
+        assert_eq!(session.login(), Ok(()));
+        assert!(!primary.is_logged() && secondary.is_logged());
+
+        assert_eq!(session.logout(), Ok(()));
+        assert!(!secondary.is_logged());
+    }
+
+    #[test]
+    fn falls_back_when_primary_shows_no_login_form() {
+        let journal = Journal::new();
+        let (primary, secondary) = (Fake::new(&journal), Fake::new(&journal));
+        primary.fail_login(Error::Portal("no login form at http://portal/".to_string()));
+        let session = Fallback::new(primary, secondary.clone());
+
+        assert_eq!(session.login(), Ok(()));
+        assert!(secondary.is_logged());
+    }
+
+    #[test]
+    fn does_not_fall_back_on_rejected_credentials() {
+        let journal = Journal::new();
+        let (primary, secondary) = (Fake::new(&journal), Fake::new(&journal));
+        primary.fail_login(Error::CredentialsMismatch);
+        let session = Fallback::new(primary, secondary.clone());
+
+        assert_eq!(session.login(), Err(Error::CredentialsMismatch));
+        assert!(!secondary.is_logged());
+    }
+}
@@ -0,0 +1,243 @@
+use std::sync::{Arc, Mutex};
+use url::Url;
+use super::error::Error;
+use super::form::Form;
+use super::transport::{self, Transport};
+use super::validation::Validation;
+use super::wispr::Message;
+
+/// Session for FON community hotspots, whose gateway redirects any plain
+/// HTTP request to a login form. Uses the FON account credentials rather
+/// than the MEO ones.
+#[derive(Debug, Clone)]
+pub struct Fon {
+    username: String,
+    password: String,
+    probe: Url,
+    transport: Transport,
+    agent: transport::Agent,
+    logoff: Arc<Mutex<Option<Url>>>
+}
+
+impl Fon {
+
+    const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+    pub fn new(username: &str, password: &str, validation: Validation) -> Result<Self, Error> {
+        const PROBE_URL: &str = "http://connectivitycheck.gstatic.com/generate_204";
+        validation.check(username, password)?;
+        Ok(Self {
+            username: username.to_string(),
+            password: password.to_string(),
+            probe: Url::parse(PROBE_URL).expect("valid probe URL"),
+            transport: Transport::default(),
+            agent: Self::agent(&Transport::default()),
+            logoff: Arc::new(Mutex::new(None))
+        })
+    }
+
+    /// Plain HTTP address that answers `204 No Content` once logged in and
+    /// that the gateway redirects to the portal otherwise.
+    #[allow(dead_code)]
+    pub fn with_probe(mut self, probe: &str) -> Result<Self, Error> {
+        self.probe = Url::parse(probe).map_err(|e| Error::Portal(e.to_string()))?;
+        Ok(self)
+    }
+
+    pub fn with_transport(mut self, transport: &Transport) -> Self {
+        self.agent = Self::agent(transport);
+        self.transport = transport.clone();
+        self
+    }
+
+    fn agent(transport: &Transport) -> transport::Agent {
+        transport.agent(|| {
+            ureq::AgentBuilder::new()
+                .timeout(Self::TIMEOUT)
+                .user_agent("Mozilla/5.0 (X11; Linux x86_64)")
+        })
+    }
+
+    fn is_online(&self) -> bool {
+        self.transport
+            .agent(|| ureq::AgentBuilder::new().timeout(Self::TIMEOUT).redirects(0))
+            .request_url("GET", &self.probe)
+            .call()
+            .is_ok_and(|response| response.status() == 204)
+    }
+
+    fn read(response: ureq::Response, fallback: &Url) -> Result<(String, Url), Error> {
+        let page = Url::parse(response.get_url()).unwrap_or_else(|_| fallback.clone());
+        Ok((response.into_string()?, page))
+    }
+
+}
+
+impl super::Trait for Fon {
+
+    fn is_logged(&self) -> bool {
+        self.is_online()
+    }
+
+    fn login(&self) -> Result<(), Error> {
+        let response = self.agent.request_url("GET", &self.probe).call()?;
+        if response.status() == 204 {
+            return Err(Error::AlreadyLoggedIn);
+        }
+        let (html, page) = Self::read(response, &self.probe)?;
+        let form = Form::parse(&html, &page)
+            .ok_or_else(|| Error::Portal("FON login form not found".to_string()))?;
+
+        let fields = form.submission(&self.username, &self.password);
+        let response = self.agent.request_url("POST", &form.action).send_form(&fields)?;
+        let (html, page) = Self::read(response, &form.action)?;
+        if Form::parse(&html, &page).is_some() {
+            return Err(Error::CredentialsMismatch);
+        }
+
+        // Some gateways answer with WISPr parameters instead of a page.
+        let reply = Message::parse(&html);
+        if let Some(reply) = reply.as_ref().filter(|reply| reply.response_code != Message::LOGIN_SUCCEEDED) {
+            return Err(reply.error());
+        }
+        let logoff = Form::link(&html, &page, "logoff")
+            .or_else(|| Form::link(&html, &page, "logout"))
+            .or_else(|| reply.as_ref().and_then(|reply| reply.logoff_url.as_deref()).and_then(|url| page.join(url).ok()));
+        if logoff.is_none() && reply.is_none() && !self.is_online() {
+            return Err(Error::Portal(format!("no sign of a login at {}", page)));
+        }
+        *self.logoff.lock().unwrap() = logoff;
+        Ok(())
+    }
+
+    fn logout(&self) -> Result<(), Error> {
+        let logoff = self.logoff.lock().unwrap().take();
+        let url = logoff.ok_or_else(|| Error::Portal("FON logoff address unknown".to_string()))?;
+        self.agent.request_url("GET", &url).call()?;
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Trait;
+    use crate::testing::{Response, Server};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    const PORTAL: &str = include_str!("fixtures/fon/portal.html");
+    const SUCCESS: &str = include_str!("fixtures/fon/success.html");
+    const FAILURE: &str = include_str!("fixtures/fon/failure.html");
+    const UNEXPECTED: &str = include_str!("fixtures/fon/unexpected.html");
+
+    /// Gateway that lets traffic through once the expected credentials
+    /// have been posted.
+    fn gateway() -> Server {
+        let online = Arc::new(AtomicBool::new(false));
+        Server::start(move |request| match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/generate_204") if online.load(Ordering::SeqCst) => Response::status(204),
+            ("GET", "/generate_204") => Response::redirect("/portal"),
+            ("GET", "/portal") => Response::ok(PORTAL),
+            ("POST", path) if path.starts_with("/login/fon") => {
+                if request.body.contains("UserName=fon%40example.pt&Password=Passw0rd") {
+                    online.store(true, Ordering::SeqCst);
+                    Response::ok(SUCCESS)
+                } else {
+                    Response::ok(FAILURE)
+                }
+            }
+            ("GET", path) if path.starts_with("/logoff") => {
+                online.store(false, Ordering::SeqCst);
+                Response::ok("")
+            }
+            _ => Response::status(404)
+        })
+    }
+
+    fn session(server: &Server, password: &str) -> Fon {
+        Fon::new("fon@example.pt", password, Validation::Off)
+            .and_then(|s| s.with_probe(&server.url("/generate_204")))
+            .unwrap()
+    }
+
+    #[test]
+    fn logs_in_through_redirected_form() {
+        let server = gateway();
+        let session = session(&server, "Passw0rd");
+
+        assert!(!session.is_logged());
+        assert_eq!(session.login(), Ok(()));
+        assert!(session.is_logged());
+
+        let post = server.requests().into_iter().find(|r| r.method == "POST").unwrap();
+        assert_eq!(post.path, "/login/fon?res=notyet&uamip=10.0.0.1&uamport=3990");
+        assert_eq!(
+            post.body,
+            "OriginatingServer=http%3A%2F%2Fconnectivitycheck.gstatic.com%2Fgenerate_204\
+             &FNAME=0&UserName=fon%40example.pt&Password=Passw0rd"
+        );
+    }
+
+    #[test]
+    fn logs_out_through_link_from_success_page() {
+        let server = gateway();
+        let session = session(&server, "Passw0rd");
+
+        session.login().unwrap();
+        assert_eq!(session.logout(), Ok(()));
+        assert!(!session.is_logged());
+        assert!(server.requests().iter().any(|r| r.path == "/logoff?uamip=10.0.0.1&uamport=3990"));
+    }
+
+    #[test]
+    fn rejected_credentials_show_form_again() {
+        let server = gateway();
+
+        assert_eq!(session(&server, "wrong").login(), Err(Error::CredentialsMismatch));
+    }
+
+    /// Gateway that never lets traffic through and answers the login with
+    /// `reply`.
+    fn closed_gateway(reply: &'static str) -> Server {
+        Server::start(move |request| match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/generate_204") => Response::redirect("/portal"),
+            ("GET", "/portal") => Response::ok(PORTAL),
+            ("POST", _) => Response::ok(reply),
+            _ => Response::status(404)
+        })
+    }
+
+    #[test]
+    fn unexpected_page_after_login_is_not_a_login() {
+        let server = closed_gateway(UNEXPECTED);
+
+        assert_eq!(
+            session(&server, "Passw0rd").login(),
+            Err(Error::Portal(format!(
+                "no sign of a login at {}",
+                server.url("/login/fon?res=notyet&uamip=10.0.0.1&uamport=3990")
+            )))
+        );
+    }
+
+    #[test]
+    fn accepts_wispr_reply_to_login() {
+        let server = closed_gateway(
+            "<WISPAccessGatewayParam><AuthenticationReply><MessageType>120</MessageType>\
+             <ResponseCode>50</ResponseCode><LogoffURL>/wispr/logoff</LogoffURL></AuthenticationReply></WISPAccessGatewayParam>"
+        );
+        let session = session(&server, "Passw0rd");
+
+        assert_eq!(session.login(), Ok(()));
+        let _ = session.logout();
+        assert_eq!(server.requests().last().unwrap().path, "/wispr/logoff");
+    }
+
+    #[test]
+    fn already_online_is_reported() {
+        let server = Server::start(|_| Response::status(204));
+
+        assert_eq!(session(&server, "Passw0rd").login(), Err(Error::AlreadyLoggedIn));
+    }
+}
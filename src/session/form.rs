@@ -0,0 +1,149 @@
+use lazy_regex::regex;
+use url::Url;
+
+/// Login form scraped from the portal page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Form {
+    pub action: Url,
+    pub fields: Vec<(String, String)>,
+    pub username_field: String,
+    pub password_field: String
+}
+
+impl Form {
+
+    pub fn decode(value: &str) -> String {
+        value
+            .replace("&quot;", "\"")
+            .replace("&#39;", "'")
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&amp;", "&")
+    }
+
+    /// Whether `tag` carries the boolean attribute `name`, with or without
+    /// a value.
+    fn flag(tag: &str, name: &str) -> bool {
+        regex!(r#"(?i)(?:"[^"]*"|'[^']*'|[^\s"'=/]+)"#)
+            .find_iter(tag)
+            .any(|token| token.as_str().eq_ignore_ascii_case(name))
+    }
+
+    fn attribute(tag: &str, name: &str) -> Option<String> {
+        regex!(r#"(?i)([a-z-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#)
+            .captures_iter(tag)
+            .find(|c| c[1].eq_ignore_ascii_case(name))
+            .and_then(|c| c.get(2).or_else(|| c.get(3)))
+            .map(|v| Self::decode(v.as_str()))
+    }
+
+    /// Finds the first form with a password input on the page.
+    pub fn parse(html: &str, page: &Url) -> Option<Self> {
+        regex!(r"(?is)<form\b([^>]*)>(.*?)</form>")
+            .captures_iter(html)
+            .find_map(|form| {
+                let action = Self::attribute(&form[1], "action")
+                    .and_then(|action| page.join(&action).ok())
+                    .unwrap_or_else(|| page.clone());
+                let mut fields = Vec::new();
+                let mut username_field = None;
+                let mut password_field = None;
+                for input in regex!(r"(?is)<input\b([^>]*)>").captures_iter(&form[2]) {
+                    let Some(name) = Self::attribute(&input[1], "name") else {
+                        continue;
+                    };
+                    let r#type = Self::attribute(&input[1], "type").unwrap_or_default().to_lowercase();
+                    match r#type.as_str() {
+                        "hidden" => fields.push((name, Self::attribute(&input[1], "value").unwrap_or_default())),
+                        "checkbox" if Self::flag(&input[1], "checked") => {
+                            fields.push((name, Self::attribute(&input[1], "value").unwrap_or("on".to_string())))
+                        }
+                        "password" => password_field = Some(name),
+                        "" | "text" | "email" | "tel" if username_field.is_none() => username_field = Some(name),
+                        _ => {}
+                    }
+                }
+                Some(Self {
+                    action,
+                    fields,
+                    username_field: username_field?,
+                    password_field: password_field?
+                })
+            })
+    }
+
+    pub fn csrf_token(&self) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(name, _)| {
+                let name = name.to_lowercase();
+                name.contains("csrf") || name.contains("verificationtoken")
+            })
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Hidden fields and ticked checkboxes followed by the credentials.
+    pub fn submission<'a>(&'a self, username: &'a str, password: &'a str) -> Vec<(&'a str, &'a str)> {
+        let mut fields: Vec<(&str, &str)> = self
+            .fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        fields.push((&self.username_field, username));
+        fields.push((&self.password_field, password));
+        fields
+    }
+
+    /// First link on the page whose address contains `needle`.
+    pub fn link(html: &str, page: &Url, needle: &str) -> Option<Url> {
+        regex!(r"(?is)<a\b([^>]*)>")
+            .captures_iter(html)
+            .filter_map(|a| Self::attribute(&a[1], "href"))
+            .find(|href| href.to_lowercase().contains(needle))
+            .and_then(|href| page.join(&href).ok())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOGIN_PAGE: &str = r#"
+        <form method="post" action="/login">
+            <input type="hidden" name="_csrf" value="t0k&amp;en">
+            <input type="email" name="user">
+            <input type='password' name='pass'>
+            <input type="checkbox" name="remember" checked/>
+            <input type="checkbox" name="newsletter" value="checked">
+            <input type="checkbox" name="terms" value="yes" CHECKED="checked">
+        </form>
+        <a href="/help">Ajuda</a> <a href="/session/logout?id=1">Sair</a>
+    "#;
+
+    #[test]
+    fn parses_login_form() {
+        let page = Url::parse("http://portal/start").unwrap();
+        let form = Form::parse(LOGIN_PAGE, &page).unwrap();
+
+        assert_eq!(form.action.as_str(), "http://portal/login");
+        assert_eq!(form.fields, vec![
+            ("_csrf".to_string(), "t0k&en".to_string()),
+            ("remember".to_string(), "on".to_string()),
+            ("terms".to_string(), "yes".to_string())
+        ]);
+        assert_eq!(form.username_field, "user");
+        assert_eq!(form.password_field, "pass");
+    }
+
+    #[test]
+    fn finds_links_by_address() {
+        let page = Url::parse("http://portal/start").unwrap();
+
+        assert_eq!(
+            Form::link(LOGIN_PAGE, &page, "logout").map(String::from),
+            Some("http://portal/session/logout?id=1".to_string())
+        );
+        assert_eq!(Form::link(LOGIN_PAGE, &page, "signup"), None);
+    }
+}
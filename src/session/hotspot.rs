@@ -0,0 +1,234 @@
+use url::Url;
+use super::error::Error;
+use super::transport::{self, Transport};
+
+/// Checks that a hotspot looks like the real thing before credentials are
+/// sent to it: the portal must be within the expected domains, resolve and
+/// present a trusted certificate, and a plain HTTP probe must either go
+/// through or be redirected into those domains. A hotspot answering the
+/// probe itself, the way an impostor serving its own login page would, is
+/// not trusted.
+#[derive(Debug, Clone)]
+pub struct Hotspot {
+    domains: Vec<String>,
+    probe: Url,
+    agent: transport::Agent
+}
+
+impl Hotspot {
+
+    const MAX_REDIRECTS: usize = 5;
+
+    pub fn new(domains: &[String]) -> Self {
+        const PROBE_URL: &str = "http://connectivitycheck.gstatic.com/generate_204";
+        Self {
+            domains: domains.iter().map(|domain| domain.trim_start_matches('.').to_lowercase()).collect(),
+            probe: Url::parse(PROBE_URL).expect("valid probe URL"),
+            agent: Self::agent(&Transport::default())
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn with_probe(mut self, probe: &str) -> Result<Self, Error> {
+        self.probe = Url::parse(probe).map_err(|e| Error::Portal(e.to_string()))?;
+        Ok(self)
+    }
+
+    pub fn with_transport(mut self, transport: &Transport) -> Self {
+        self.agent = Self::agent(transport);
+        self
+    }
+
+    fn agent(transport: &Transport) -> transport::Agent {
+        const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+        transport.agent(|| ureq::AgentBuilder::new().timeout(TIMEOUT).redirects(0))
+    }
+
+    fn is_expected(&self, host: &str) -> bool {
+        let host = host.to_lowercase();
+        self.domains.iter().any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)))
+    }
+
+    /// Resolves the portal host and completes the TLS handshake through the
+    /// shared transport, so its trust store and pins apply. Any HTTP answer
+    /// will do.
+    fn verify_portal(&self, portal: &Url) -> Result<(), Error> {
+        match self.agent.request_url("HEAD", portal).call().map_err(Error::from) {
+            Ok(_) | Err(Error::Status(_)) => Ok(()),
+            Err(error @ Error::PinMismatch(_)) => Err(error),
+            Err(error) => Err(Error::Untrusted(format!("portal {} is unreachable: {}", portal, error)))
+        }
+    }
+
+    /// Follows the captive redirect of the probe. Every hop must stay within
+    /// the expected domains and at least one must be taken, unless the probe
+    /// goes through untouched. Failing to reach the probe at all says nothing
+    /// about the hotspot, so it is reported as is rather than as untrusted.
+    fn verify_redirects(&self) -> Result<(), Error> {
+        let mut url = self.probe.clone();
+        for hop in 0..=Self::MAX_REDIRECTS {
+            let (status, location) = match self.agent.request_url("GET", &url).call() {
+                Ok(response) => (response.status(), response.header("Location").map(str::to_string)),
+                Err(ureq::Error::Status(status, _)) => (status, None),
+                Err(error) => return Err(Error::from(error))
+            };
+            let location = match location.filter(|_| status / 100 == 3) {
+                Some(location) => location,
+                None if hop > 0 => return Ok(()),
+                None if status == 204 => return Ok(()),
+                None => {
+                    return Err(Error::Untrusted(format!(
+                        "the hotspot answered the probe with HTTP status {} instead of redirecting to the portal",
+                        status
+                    )))
+                }
+            };
+            url = url.join(&location).map_err(|e| Error::Untrusted(format!("bad captive redirect: {}", e)))?;
+            let host = url.host_str().unwrap_or_default();
+            if !self.is_expected(host) {
+                return Err(Error::Untrusted(format!("captive portal redirects to unexpected host {}", host)));
+            }
+        }
+        Err(Error::Untrusted("too many captive redirects".to_string()))
+    }
+
+    pub fn verify(&self, portal: Option<&Url>) -> Result<(), Error> {
+        if let Some(portal) = portal {
+            let host = portal.host_str().unwrap_or_default();
+            if !self.is_expected(host) {
+                return Err(Error::Untrusted(format!("portal host {} is not an expected domain", host)));
+            }
+            self.verify_portal(portal)?;
+        }
+        self.verify_redirects()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tls::tests as tls;
+    use crate::testing::{Response, Server};
+
+    fn hotspot(server: &Server) -> Hotspot {
+        Hotspot::new(&["meo.pt".to_string(), "127.0.0.1".to_string(), "localhost".to_string()])
+            .with_probe(&server.url("/generate_204"))
+            .unwrap()
+    }
+
+    /// Transport trusting the test CA the `tls` test server is signed by.
+    fn trusting_test_ca() -> Transport {
+        let path = std::env::temp_dir().join(format!("meo-wifi-hotspot-ca-{}.pem", std::process::id()));
+        std::fs::write(&path, tls::CA).unwrap();
+        let transport = Transport::default().with_trust_store(&path);
+        let _ = std::fs::remove_file(&path);
+        transport.unwrap()
+    }
+
+    #[test]
+    fn accepts_redirects_within_expected_domains() {
+        let portal = Server::start(|request| match request.path.as_str() {
+            "/portal" => Response::redirect("/login"),
+            _ => Response::ok("login")
+        });
+        let port = portal.url("").rsplit(':').next().unwrap().to_string();
+        let server = Server::start(move |request| match request.path.as_str() {
+            "/generate_204" => Response::redirect(&format!("http://portal.meo.pt:{}/portal", port)),
+            _ => Response::status(404)
+        });
+        let resolver = super::super::Resolver::default().with_hosts("portal.meo.pt=127.0.0.1").unwrap();
+        let hotspot = hotspot(&server).with_transport(&Transport::default().with_resolver(resolver));
+
+        assert_eq!(hotspot.verify(None), Ok(()));
+    }
+
+    #[test]
+    fn rejects_redirect_to_foreign_domain() {
+        let server = Server::start(|_| Response::redirect("http://meo-wifi-login.example.com/"));
+
+        assert_eq!(
+            hotspot(&server).verify(None),
+            Err(Error::Untrusted("captive portal redirects to unexpected host meo-wifi-login.example.com".to_string()))
+        );
+    }
+
+    #[test]
+    fn accepts_hotspot_that_is_already_open() {
+        let server = Server::start(|_| Response::status(204));
+        let portal = Url::parse(&tls::server("portal")).unwrap();
+
+        assert_eq!(hotspot(&server).with_transport(&trusting_test_ca()).verify(Some(&portal)), Ok(()));
+    }
+
+    #[test]
+    fn rejects_portal_with_untrusted_certificate() {
+        let server = Server::start(|_| Response::status(204));
+        let portal = Url::parse(&tls::server("portal")).unwrap();
+
+        assert!(matches!(
+            hotspot(&server).verify(Some(&portal)),
+            Err(Error::Untrusted(reason)) if reason.starts_with(&format!("portal {} is unreachable", portal))
+        ));
+    }
+
+    #[test]
+    fn rejects_portal_whose_key_is_not_pinned() {
+        let server = Server::start(|_| Response::status(204));
+        let portal = Url::parse(&tls::server("portal")).unwrap();
+        let other = "sha256/AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".parse().unwrap();
+        let transport = trusting_test_ca().with_pins(&[other]);
+
+        assert_eq!(
+            hotspot(&server).with_transport(&transport).verify(Some(&portal)),
+            Err(Error::PinMismatch(tls::PORTAL_PIN.to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_portal_that_does_not_resolve() {
+        let server = Server::start(|_| Response::status(204));
+        let portal = Url::parse("https://servicoswifi.apps.meo.pt/").unwrap();
+        let resolver = super::super::Resolver::default().with_nameserver("127.0.0.1:9".parse().unwrap());
+        let hotspot = hotspot(&server).with_transport(&Transport::default().with_resolver(resolver));
+
+        assert!(matches!(
+            hotspot.verify(Some(&portal)),
+            Err(Error::Untrusted(reason)) if reason.starts_with("portal https://servicoswifi.apps.meo.pt/ is unreachable")
+        ));
+    }
+
+    #[test]
+    fn rejects_hotspot_answering_probe_itself() {
+        let server = Server::start(|_| Response::ok("<form action=\"/login\"></form>"));
+
+        assert_eq!(
+            hotspot(&server).verify(None),
+            Err(Error::Untrusted(
+                "the hotspot answered the probe with HTTP status 200 instead of redirecting to the portal".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn rejects_portal_outside_expected_domains() {
+        let server = Server::start(|_| Response::status(204));
+        let portal = Url::parse("https://meo-wifi-login.example.com/").unwrap();
+
+        assert_eq!(
+            hotspot(&server).verify(Some(&portal)),
+            Err(Error::Untrusted("portal host meo-wifi-login.example.com is not an expected domain".to_string()))
+        );
+    }
+
+    #[test]
+    fn unreachable_probe_is_not_a_verdict() {
+        let resolver = super::super::Resolver::default().with_nameserver("127.0.0.1:9".parse().unwrap());
+        let hotspot = Hotspot::new(&["meo.pt".to_string()])
+            .with_probe("http://connectivitycheck.example/generate_204")
+            .unwrap()
+            .with_transport(&Transport::default().with_resolver(resolver));
+
+        assert!(matches!(hotspot.verify(None), Err(error) if error.kind() == crate::error::Kind::Network));
+    }
+}
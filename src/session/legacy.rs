@@ -1,42 +1,143 @@
 use aes::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
 use base64::prelude::{BASE64_STANDARD as base64, Engine};
+use cookie_store::CookieStore;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::os::unix::fs::OpenOptionsExt;
 use std::net::UdpSocket;
-use lazy_regex::regex;
+use std::path::PathBuf;
+use std::time::Duration;
+use url::Url;
 use super::error::Error;
+use super::tls::Pin;
+use super::transport::{self, Transport};
+use super::validation::Validation;
+use crate::log;
 
 #[derive(Debug, Clone)]
 pub struct Legacy {
     username: String,
     password: String,
+    portal: String,
+    timeout: Duration,
+    user_agent: String,
+    cookie_file: Option<PathBuf>,
+    transport: Transport,
+    pins: Vec<Pin>,
+    agent: transport::Agent
 }
 
 impl Legacy {
 
-    fn validate_credentials(username: &str, password: &str) -> Result<(), Error> {
+    pub fn new(username: &str, password: &str, validation: Validation) -> Result<Self, Error> {
+        const PORTAL_URL: &str = "https://servicoswifi.apps.meo.pt/HotspotConnection.svc";
+        const TIMEOUT: Duration = Duration::from_secs(2);
+        const USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64)";
+        validation.check(username, password)?;
+        Ok(Self {
+            username: username.to_string(),
+            password: password.to_string(),
+            portal: PORTAL_URL.to_string(),
+            timeout: TIMEOUT,
+            user_agent: USER_AGENT.to_string(),
+            cookie_file: None,
+            transport: Transport::default(),
+            pins: Vec::new(),
+            agent: Self::agent(TIMEOUT, USER_AGENT, CookieStore::default(), &Transport::default())
+        })
+    }
 
-        let email_regex = regex!(r"^[a-zA-Z0-9._%+-]+@[a-zA-Z0-9.-]+\.[a-zA-Z]{2,}$");
-        if !email_regex.is_match(username) {
-            return Err(Error::InvalidEmail);
-        }
+    #[allow(dead_code)]
+    pub fn with_portal(mut self, portal: &str) -> Self {
+        self.portal = portal.trim_end_matches('/').to_string();
+        self
+    }
 
-        let has_lowercase = || password.chars().any(|c| c.is_ascii_lowercase());
-        let has_uppercase = || password.chars().any(|c| c.is_ascii_uppercase());
-        let has_number = || password.chars().any(|c| c.is_ascii_digit());
-        if password.len() < 8 || !has_lowercase() || !has_uppercase() || !has_number() {
-            return Err(Error::InvalidPassword);
-        }
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self.rebuild_agent();
+        self
+    }
 
-        Ok(())
+    pub fn with_user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_string();
+        self.rebuild_agent();
+        self
     }
 
-    pub fn new(username: &str, password: &str) -> Result<Self, Error> {
-        Self::validate_credentials(username, password)?;
-        Ok(Self {
-            username: username.to_string(),
-            password: password.to_string(),
+    pub fn with_transport(mut self, transport: &Transport) -> Self {
+        self.transport = transport.clone().with_pins(&self.pins);
+        self.rebuild_agent();
+        self
+    }
+
+    /// Only talks to the portal when its certificate key matches one of
+    /// `pins`, so credentials are never sent to an impostor.
+    pub fn with_pins(mut self, pins: &[Pin]) -> Self {
+        self.pins = pins.to_vec();
+        self.transport = self.transport.with_pins(pins);
+        self.rebuild_agent();
+        self
+    }
+
+    /// Keeps the portal cookies in `path`, loading whatever an earlier run
+    /// left there and saving them back after every request.
+    pub fn with_cookie_file(mut self, path: impl Into<PathBuf>) -> Result<Self, Error> {
+        let path = path.into();
+        let cookies = match File::open(&path) {
+            Ok(file) => cookie_store::serde::json::load(BufReader::new(file))
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => CookieStore::default(),
+            Err(e) => return Err(e.into())
+        };
+        self.agent = Self::agent(self.timeout, &self.user_agent, cookies, &self.transport);
+        self.cookie_file = Some(path);
+        Ok(self)
+    }
+
+    fn agent(timeout: Duration, user_agent: &str, cookies: CookieStore, transport: &Transport) -> transport::Agent {
+        transport.agent(|| {
+            ureq::AgentBuilder::new()
+                .timeout(timeout)
+                .user_agent(user_agent)
+                .cookie_store(cookies.clone())
         })
     }
 
+    fn rebuild_agent(&mut self) {
+        let cookies = match self.url("") {
+            Ok(url) => self.agent.for_url(&url).cookie_store().clone(),
+            Err(_) => CookieStore::default()
+        };
+        self.agent = Self::agent(self.timeout, &self.user_agent, cookies, &self.transport);
+    }
+
+    fn url(&self, path: &str) -> Result<Url, Error> {
+        Url::parse(&format!("{}/{}", self.portal, path)).map_err(|e| Error::Portal(e.to_string()))
+    }
+
+    /// Writes the cookies readable by the owner only, to a temporary file
+    /// renamed over `path`, so an interrupted write leaves the old one.
+    fn save_cookies(&self, cookies: &CookieStore) -> Result<(), Error> {
+        let Some(path) = &self.cookie_file else {
+            return Ok(());
+        };
+        let mut temporary = path.clone().into_os_string();
+        temporary.push(format!(".{}.tmp", std::process::id()));
+        let temporary = PathBuf::from(temporary);
+        let file = OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&temporary)?;
+        let mut writer = BufWriter::new(file);
+        let written = cookie_store::serde::json::save(cookies, &mut writer)
+            .map_err(std::io::Error::other)
+            .and_then(|_| writer.flush())
+            .and_then(|_| writer.get_ref().sync_all())
+            .and_then(|_| std::fs::rename(&temporary, path));
+        if written.is_err() {
+            let _ = std::fs::remove_file(&temporary);
+        }
+        Ok(written?)
+    }
+
     pub fn get_local_ip() -> Option<String> {
         let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
         socket.connect("8.8.8.8:80").ok()?;
@@ -44,7 +145,11 @@ impl Legacy {
         Some(local_addr.ip().to_string())
     }
 
-    fn encrypt_password(&self) -> String {
+    /// Encrypts the password the way the portal's own page does: AES-256-CBC
+    /// with PKCS#7 padding, keyed by PBKDF2-SHA1 over the client's IP address
+    /// with a fixed salt and IV, then base64 encoded.
+    pub fn encrypt_password(password: &str, ip: &str) -> Result<String, Error> {
+        const BLOCK_SIZE: usize = 16;
         const SALT: [u8; 19] = [
             0x77, 0x23, 0x24, 0x69, 0x66, 0x69, 0x31, 0x32, 0x34, 0x29,
             0x39, 0x6D, 0x65, 0x6F, 0x39, 0x38, 0x57, 0x49, 0x46
@@ -54,26 +159,27 @@ impl Legacy {
             0x72, 0xc4, 0x72, 0x1a, 0xe0, 0x1a, 0xe0, 0xe8,
             0xe8, 0x4b, 0xd6, 0x4a, 0xd6, 0x60, 0x60, 0xc4
         ];
-        let ip = Self::get_local_ip().expect("Failed to get local IP address");
         let key = pbkdf2::pbkdf2_hmac_array::<sha1::Sha1, 32>(ip.as_bytes(), &SALT, 100);
 
-        let mut buffer = [0u8; 48];
+        let plaintext = password.as_bytes();
+        let mut buffer = vec![0u8; (plaintext.len() / BLOCK_SIZE + 1) * BLOCK_SIZE];
         let cipher = cbc::Encryptor::<aes::Aes256>::new(&key.into(),&IV.into());
         let ciphertext = cipher
-            .encrypt_padded_b2b_mut::<Pkcs7>(self.password.as_bytes(), &mut buffer)
-            .expect("Failed to encrypt password");
+            .encrypt_padded_b2b_mut::<Pkcs7>(plaintext, &mut buffer)
+            .map_err(|e| Error::Encryption(e.to_string()))?;
 
-        base64.encode(&ciphertext)
+        Ok(base64.encode(ciphertext))
     }
 
-    fn send_request(&self, url: &str) -> Result<ureq::Response, Error> {
-        const USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64)";
+    fn send_request(&self, path: &str) -> Result<ureq::Response, Error> {
         const CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
-        let res = ureq::get(url)
+        let url = self.url(path)?;
+        let agent = self.agent.for_url(&url);
+        let res = agent
+            .request_url("GET", &url)
             .set("Content-Type", CONTENT_TYPE)
-            .set("User-Agent", USER_AGENT)
-            .timeout(std::time::Duration::from_secs(2))
             .send_form(&[])?;
+        let _ = self.save_cookies(&agent.cookie_store()).map_err(log::error);
         Ok(res)
     }
 
@@ -82,9 +188,8 @@ impl Legacy {
 impl super::Trait for Legacy {
 
     fn is_logged(&self) -> bool {
-        const URL: &str = "https://servicoswifi.apps.meo.pt/HotspotConnection.svc/GetState?mobile=false";
         self
-            .send_request(URL)
+            .send_request("GetState?mobile=false")
             .ok()
             .and_then(|r| {
                 r
@@ -95,14 +200,15 @@ impl super::Trait for Legacy {
     }
 
     fn login(&self) -> Result<(), Error> {
-        let url = format!(
-            "https://servicoswifi.apps.meo.pt/HotspotConnection.svc/Login?username={}&password={}&navigatorLang=en&callback=",
+        let ip = Self::get_local_ip().ok_or(Error::NetworkUnreachable)?;
+        let path = format!(
+            "Login?username={}&password={}&navigatorLang=en&callback=",
             urlencoding::encode(&self.username),
-            urlencoding::encode(&self.encrypt_password())
+            urlencoding::encode(&Self::encrypt_password(&self.password, &ip)?)
         );
 
         let json: serde_json::Value = self
-            .send_request(&url)
+            .send_request(&path)
             .and_then(|r| r.into_json().map_err(Error::from))?;
 
         let is_successful = json
@@ -128,25 +234,143 @@ impl super::Trait for Legacy {
                 } else if e.contains("already logged") {
                     Error::AlreadyLoggedIn
                 } else {
-                    Error::Custom(e)
+                    Error::Portal(e)
                 }
-            }).unwrap_or(Error::from("Unknown error occurred"));
+            }).unwrap_or(Error::Portal("unknown error occurred".to_string()));
 
         Err(error)
     }
 
+    fn portal(&self) -> Option<Url> {
+        Url::parse(&self.portal).ok()
+    }
+
     fn logout(&self) -> Result<(), Error> {
-        const URL: &str = "https://servicoswifi.apps.meo.pt/HotspotConnection.svc/Logoff?callback=";
         let result = self
-            .send_request(URL)
+            .send_request("Logoff?callback=")
             .and_then(|r| r.into_string().map_err(Error::from))
             .map(|r| r.contains("true"));
 
         match result {
             Ok(true) => Ok(()),
-            Ok(false) => Err(Error::from("failed to logout")),
-            Err(e) => Err(Error::from(e))
+            Ok(false) => Err(Error::Portal("failed to logout".to_string())),
+            Err(e) => Err(e)
         }
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Trait;
+    use crate::testing::{Response, Server};
+    use super::super::tls::tests as tls;
+
+    #[test]
+    fn encrypts_password_with_ip_derived_key() {
+        assert_eq!(
+            Legacy::encrypt_password("Password1", "10.0.0.1"),
+            Ok("X1wdkXENVPs9kNcl3KKJMQ==".to_string())
+        );
+    }
+
+    #[test]
+    fn encrypts_empty_password_to_a_padding_block() {
+        assert_eq!(
+            Legacy::encrypt_password("", "10.0.0.1"),
+            Ok("PUow8PvpG+ouvEZLwa2+Yw==".to_string())
+        );
+    }
+
+    #[test]
+    fn encrypts_passwords_longer_than_three_blocks() {
+        let password = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+        assert_eq!(
+            Legacy::encrypt_password(password, "192.168.1.23"),
+            Ok(concat!(
+                "vGCGKW2BUAYzfQO8Es/uqOQdOTi9+KxtUQh9IBSx0CruIzefqv+S9CVcCjj/IOlK",
+                "9+j0mFJawAtxKkImKH6UJQ=="
+            ).to_string())
+        );
+    }
+
+    #[test]
+    fn pads_block_aligned_passwords_with_a_full_block() {
+        let encrypted = Legacy::encrypt_password(&"A1b".repeat(16), "10.0.0.1").unwrap();
+        assert_eq!(base64.decode(encrypted).unwrap().len(), 64);
+    }
+
+    fn session(server: &Server) -> Legacy {
+        Legacy::new("user@example.pt", "Passw0rd", Validation::Strict)
+            .unwrap()
+            .with_portal(&server.url("/svc"))
+    }
+
+    #[test]
+    fn reuses_portal_cookies_across_requests() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/svc/GetState?mobile=false" => Response::ok(r#"{"LoggedOn": true}"#)
+                .header("Set-Cookie", "ASP.NET_SessionId=abc; Path=/"),
+            _ => Response::ok("true")
+        });
+        let session = session(&server).with_user_agent("meo-wifi-test");
+
+        assert!(session.is_logged());
+        assert_eq!(session.logout(), Ok(()));
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("User-Agent"), Some("meo-wifi-test"));
+        assert_eq!(requests[1].header("Cookie"), Some("ASP.NET_SessionId=abc"));
+    }
+
+    #[test]
+    fn persists_cookies_between_sessions() {
+        let path = std::env::temp_dir().join(format!("meo-wifi-cookies-{}.json", std::process::id()));
+        let server = Server::start(|request| match request.path.as_str() {
+            "/svc/GetState?mobile=false" => Response::ok(r#"{"LoggedOn": false}"#)
+                .header("Set-Cookie", "sid=abc; Path=/; Max-Age=3600"),
+            _ => Response::ok("true")
+        });
+
+        assert!(!session(&server).with_cookie_file(&path).unwrap().is_logged());
+        assert_eq!(session(&server).with_cookie_file(&path).unwrap().logout(), Ok(()));
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(server.requests()[1].header("Cookie"), Some("sid=abc"));
+    }
+
+    #[test]
+    fn rejects_corrupted_cookie_file() {
+        let path = std::env::temp_dir().join(format!("meo-wifi-corrupted-{}.json", std::process::id()));
+        std::fs::write(&path, "not json").unwrap();
+
+        let result = Legacy::new("user@example.pt", "Passw0rd", Validation::Strict)
+            .and_then(|session| session.with_cookie_file(&path));
+        let _ = std::fs::remove_file(&path);
+
+        assert!(matches!(result, Err(Error::Malformed(_))));
+    }
+
+    #[test]
+    fn refuses_portal_whose_key_is_not_pinned() {
+        let path = std::env::temp_dir().join(format!("meo-wifi-legacy-ca-{}.pem", std::process::id()));
+        std::fs::write(&path, tls::CA).unwrap();
+        let transport = Transport::default().with_trust_store(&path);
+        let _ = std::fs::remove_file(&path);
+        let session = Legacy::new("user@example.pt", "Passw0rd", Validation::Strict)
+            .unwrap()
+            .with_portal(&tls::server("true"))
+            .with_transport(&transport.unwrap());
+
+        assert_eq!(session.clone().with_pins(&[tls::PORTAL_PIN.parse().unwrap()]).logout(), Ok(()));
+
+        let other = "sha256/AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".parse().unwrap();
+        assert_eq!(
+            session.with_pins(&[other]).logout(),
+            Err(Error::PinMismatch(tls::PORTAL_PIN.to_string()))
+        );
+    }
+}
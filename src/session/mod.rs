@@ -1,11 +1,115 @@
 mod error;
+mod form;
 mod legacy;
+mod modern;
+mod fallback;
+mod pool;
+mod scripted;
+mod fon;
+mod wispr;
+pub (super) mod captive;
+mod proxy;
+mod resolver;
+mod transport;
+mod tls;
+mod hotspot;
+mod validation;
+#[cfg(test)]
+mod fake;
 
 pub (super) use legacy::Legacy;
+pub (super) use modern::Modern;
+pub (super) use fallback::Fallback;
+pub (super) use pool::Pool;
+pub (super) use scripted::Scripted;
+pub (super) use fon::Fon;
+pub (super) use wispr::Wispr;
 pub (super) use error::Error;
+pub (super) use validation::Validation;
+pub (super) use proxy::Proxy;
+pub (super) use resolver::{Nameserver, Resolver};
+pub (super) use transport::Transport;
+pub (super) use tls::Pin;
+pub (super) use hotspot::Hotspot;
+#[cfg(test)]
+pub (super) use fake::Fake;
+
+use crate::log;
 
 pub (super) trait Trait: Clone {
     fn is_logged(&self) -> bool;
     fn login(&self) -> Result<(), error::Error>;
     fn logout(&self) -> Result<(), error::Error>;
-}
\ No newline at end of file
+
+    /// Portal endpoint credentials are sent to, when known up front, so it
+    /// can be checked before logging in.
+    fn portal(&self) -> Option<url::Url> {
+        None
+    }
+
+    /// Username of the account in use, when the session picks among several.
+    fn account(&self) -> Option<String> {
+        None
+    }
+
+    /// Login attempts recorded for each account, when the session limits
+    /// them itself.
+    fn limits(&self) -> Option<String> {
+        None
+    }
+
+    /// Logs in, settling the case where the portal claims we already are.
+    /// That reply may be stale, so the portal state is checked again and,
+    /// if it still says logged out, a full logout/login cycle is forced.
+    fn reconcile_login(&self) -> Result<(), error::Error> {
+        match self.login() {
+            Err(error::Error::AlreadyLoggedIn) => {
+                if self.is_logged() {
+                    log::info("Portal reports session already active");
+                    return Ok(());
+                }
+                log::warn("Portal reports a stale session, forcing logout");
+                let _ = self.logout().map_err(log::error);
+                self.login()
+            }
+            result => result
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Event, Journal};
+
+    #[test]
+    fn already_logged_in_is_success_when_portal_agrees() {
+        let journal = Journal::new();
+        let session = Fake::new(&journal).logged(true);
+        session.fail_login(Error::AlreadyLoggedIn);
+
+        assert_eq!(session.reconcile_login(), Ok(()));
+        assert_eq!(journal.events(), vec![Event::Login]);
+    }
+
+    #[test]
+    fn stale_session_forces_logout_and_login() {
+        let journal = Journal::new();
+        let session = Fake::new(&journal);
+        session.fail_login(Error::AlreadyLoggedIn);
+
+        assert_eq!(session.reconcile_login(), Ok(()));
+        assert_eq!(journal.events(), vec![Event::Login, Event::Logout, Event::Login]);
+        assert!(session.is_logged());
+    }
+
+    #[test]
+    fn gives_up_when_portal_keeps_refusing() {
+        let journal = Journal::new();
+        let session = Fake::new(&journal);
+        session.fail_login(Error::AlreadyLoggedIn);
+        session.fail_login(Error::AlreadyLoggedIn);
+
+        assert_eq!(session.reconcile_login(), Err(Error::AlreadyLoggedIn));
+    }
+}
@@ -0,0 +1,287 @@
+use std::sync::{Arc, Mutex};
+use url::Url;
+use super::error::Error;
+use super::form::Form;
+use super::transport::{self, Transport};
+use super::validation::Validation;
+
+/// Session for the current MEO WiFi portal, which serves a regular HTML
+/// login form protected by a CSRF token and keeps the session in cookies.
+/// The portal address isn't fixed: it is where the hotspot redirects a
+/// plain HTTP request, unless one is given.
+#[derive(Debug, Clone)]
+pub struct Modern {
+    username: String,
+    password: String,
+    probe: Url,
+    portal: Option<Url>,
+    agent: transport::Agent,
+    logout: Arc<Mutex<Option<Url>>>
+}
+
+/// What the portal shows when asked for the login page.
+enum Page {
+    Login(Form),
+    /// The probe went through or the portal links to a logout.
+    LoggedIn(Option<Url>),
+    Unknown(Url)
+}
+
+impl Modern {
+
+    pub fn new(username: &str, password: &str, validation: Validation) -> Result<Self, Error> {
+        const PROBE_URL: &str = "http://connectivitycheck.gstatic.com/generate_204";
+        validation.check(username, password)?;
+        Ok(Self {
+            username: username.to_string(),
+            password: password.to_string(),
+            probe: Url::parse(PROBE_URL).expect("valid probe URL"),
+            portal: None,
+            agent: Self::agent(&Transport::default()),
+            logout: Arc::new(Mutex::new(None))
+        })
+    }
+
+    pub fn with_transport(mut self, transport: &Transport) -> Self {
+        self.agent = Self::agent(transport);
+        self
+    }
+
+    fn agent(transport: &Transport) -> transport::Agent {
+        const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+        transport.agent(|| {
+            ureq::AgentBuilder::new()
+                .timeout(TIMEOUT)
+                .user_agent("Mozilla/5.0 (X11; Linux x86_64)")
+        })
+    }
+
+    /// Plain HTTP address that answers `204 No Content` once logged in and
+    /// that the hotspot redirects to the portal otherwise.
+    #[allow(dead_code)]
+    pub fn with_probe(mut self, probe: &str) -> Result<Self, Error> {
+        self.probe = Url::parse(probe).map_err(|e| Error::Portal(e.to_string()))?;
+        Ok(self)
+    }
+
+    /// Loads the login page from `portal` instead of following the hotspot
+    /// redirect.
+    #[allow(dead_code)]
+    pub fn with_portal(mut self, portal: &str) -> Result<Self, Error> {
+        self.portal = Some(Url::parse(portal).map_err(|e| Error::Portal(e.to_string()))?);
+        Ok(self)
+    }
+
+    /// Whether the probe now answers `204 No Content` itself, which only
+    /// happens once the hotspot lets traffic through.
+    fn probe_goes_through(&self) -> bool {
+        self.agent.request_url("GET", &self.probe).call().is_ok_and(|response| response.status() == 204)
+    }
+
+    fn fetch_page(&self) -> Result<Page, Error> {
+        let entry = self.portal.as_ref().unwrap_or(&self.probe);
+        let response = self.agent.request_url("GET", entry).call()?;
+        if response.status() == 204 {
+            return Ok(Page::LoggedIn(None));
+        }
+        let page = Url::parse(response.get_url()).unwrap_or_else(|_| entry.clone());
+        let html = response.into_string()?;
+        Ok(match (Form::parse(&html, &page), Form::link(&html, &page, "logout")) {
+            (Some(form), _) => Page::Login(form),
+            (None, Some(logout)) => Page::LoggedIn(Some(logout)),
+            (None, None) => Page::Unknown(page)
+        })
+    }
+
+}
+
+impl super::Trait for Modern {
+
+    fn is_logged(&self) -> bool {
+        matches!(self.fetch_page(), Ok(Page::LoggedIn(_)))
+    }
+
+    fn login(&self) -> Result<(), Error> {
+        let form = match self.fetch_page()? {
+            Page::Login(form) => form,
+            Page::LoggedIn(logout) => {
+                if logout.is_some() {
+                    *self.logout.lock().unwrap() = logout;
+                }
+                return Err(Error::AlreadyLoggedIn);
+            }
+            Page::Unknown(page) => return Err(Error::Portal(format!("no login form at {}", page)))
+        };
+        if form.csrf_token().is_none() {
+            return Err(Error::Portal("login form has no CSRF token".to_string()));
+        }
+
+        let fields = form.submission(&self.username, &self.password);
+        let response = self.agent.request_url("POST", &form.action).send_form(&fields)?;
+        let page = Url::parse(response.get_url()).unwrap_or_else(|_| form.action.clone());
+        let html = response.into_string()?;
+        if Form::parse(&html, &page).is_some() {
+            return Err(Error::CredentialsMismatch);
+        }
+        let logout = match Form::link(&html, &page, "logout") {
+            Some(logout) => logout,
+            None if self.probe_goes_through() => form.action.join("logout").map_err(|e| Error::Portal(e.to_string()))?,
+            None => return Err(Error::Portal(format!("no sign of a login at {}", page)))
+        };
+        *self.logout.lock().unwrap() = Some(logout);
+        Ok(())
+    }
+
+    fn portal(&self) -> Option<Url> {
+        self.portal.clone()
+    }
+
+    fn logout(&self) -> Result<(), Error> {
+        let known = self.logout.lock().unwrap().take();
+        let url = match known.or_else(|| self.portal.as_ref().and_then(|portal| portal.join("logout").ok())) {
+            Some(url) => url,
+            None => return Err(Error::Portal("logout address unknown".to_string()))
+        };
+        self.agent.request_url("GET", &url).call()?;
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Trait;
+    use crate::testing::{Response, Server};
+
+    const LOGIN_PAGE: &str = r#"
+        <html><body>
+        <form method="post" action="/login">
+            <input type="hidden" name="_csrf" value="t0k&amp;en">
+            <input type="email" name="user">
+            <input type='password' name='pass'>
+            <button type="submit">Entrar</button>
+        </form>
+        </body></html>
+    "#;
+
+    fn session(server: &Server) -> Modern {
+        Modern::new("user@example.pt", "Passw0rd", Validation::Strict)
+            .and_then(|s| s.with_portal(&server.url("/")))
+            .and_then(|s| s.with_probe(&server.url("/generate_204")))
+            .unwrap()
+    }
+
+    #[test]
+    fn posts_credentials_with_token_and_cookie() {
+        let server = Server::start(|request| match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/") => Response::ok(LOGIN_PAGE).header("Set-Cookie", "sid=abc; Path=/"),
+            ("POST", "/login") => Response::redirect("/welcome"),
+            _ => Response::ok("<p>Bem-vindo</p><a href=\"/logout\">Sair</a>")
+        });
+
+        assert_eq!(session(&server).login(), Ok(()));
+
+        let requests = server.requests();
+        let post = requests.iter().find(|r| r.method == "POST").unwrap();
+        assert_eq!(post.body, "_csrf=t0k%26en&user=user%40example.pt&pass=Passw0rd");
+        assert_eq!(post.header("Cookie"), Some("sid=abc"));
+        assert_eq!(requests.last().unwrap().path, "/welcome");
+    }
+
+    #[test]
+    fn accepts_login_once_probe_goes_through() {
+        let server = Server::start(|request| match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/") => Response::ok(LOGIN_PAGE),
+            ("POST", "/login") => Response::ok("<p>Bem-vindo</p>"),
+            _ => Response::status(204)
+        });
+        let session = session(&server);
+
+        assert_eq!(session.login(), Ok(()));
+        assert_eq!(session.logout(), Ok(()));
+        assert_eq!(server.requests().last().unwrap().path, "/logout");
+    }
+
+    #[test]
+    fn unrelated_page_after_login_is_not_a_login() {
+        let server = Server::start(|request| match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/") => Response::ok(LOGIN_PAGE),
+            ("POST", "/login") => Response::redirect("/maintenance"),
+            _ => Response::ok("<p>Em manutenção</p>")
+        });
+
+        assert_eq!(
+            session(&server).login(),
+            Err(Error::Portal(format!("no sign of a login at {}", server.url("/maintenance"))))
+        );
+    }
+
+    #[test]
+    fn rejected_credentials_show_form_again() {
+        let server = Server::start(|_| Response::ok(LOGIN_PAGE));
+
+        assert_eq!(session(&server).login(), Err(Error::CredentialsMismatch));
+    }
+
+    #[test]
+    fn refuses_form_without_csrf_token() {
+        let server = Server::start(|_| Response::ok(&LOGIN_PAGE.replace("_csrf", "next")));
+
+        assert_eq!(
+            session(&server).login(),
+            Err(Error::Portal("login form has no CSRF token".to_string()))
+        );
+    }
+
+    #[test]
+    fn logged_in_when_portal_shows_no_form() {
+        let server = Server::start(|_| Response::ok("<a href=\"/logout\">Sair</a>"));
+
+        assert!(session(&server).is_logged());
+        assert_eq!(session(&server).login(), Err(Error::AlreadyLoggedIn));
+    }
+
+    #[test]
+    fn page_without_form_or_logout_is_not_a_login() {
+        let server = Server::start(|_| Response::ok("<p>Erro interno</p>"));
+
+        assert!(!session(&server).is_logged());
+        assert!(matches!(session(&server).login(), Err(Error::Portal(_))));
+    }
+
+    #[test]
+    fn finds_portal_through_hotspot_redirect() {
+        let server = Server::start(|request| match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/generate_204") => Response::redirect("/portal"),
+            ("GET", "/portal") => Response::ok(LOGIN_PAGE),
+            ("POST", "/login") => Response::ok("<a href=\"/sair/logout\">Sair</a>"),
+            _ => Response::ok("")
+        });
+        let session = Modern::new("user@example.pt", "Passw0rd", Validation::Strict)
+            .and_then(|s| s.with_probe(&server.url("/generate_204")))
+            .unwrap();
+
+        assert_eq!(session.login(), Ok(()));
+        assert_eq!(session.logout(), Ok(()));
+        assert_eq!(server.requests().last().unwrap().path, "/sair/logout");
+    }
+
+    #[test]
+    fn logged_in_when_probe_goes_through() {
+        let server = Server::start(|_| Response::status(204));
+        let session = Modern::new("user@example.pt", "Passw0rd", Validation::Strict)
+            .and_then(|s| s.with_probe(&server.url("/generate_204")))
+            .unwrap();
+
+        assert!(session.is_logged());
+        assert_eq!(session.login(), Err(Error::AlreadyLoggedIn));
+    }
+
+    #[test]
+    fn missing_portal_is_a_status_error() {
+        let server = Server::start(|_| Response::status(404));
+
+        assert_eq!(session(&server).login(), Err(Error::Status(404)));
+    }
+}
@@ -0,0 +1,261 @@
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use super::error::Error;
+use crate::executor::Limiter;
+use crate::log;
+
+/// Rotates through several accounts, moving on to the next one when the
+/// portal rejects the credentials or the account is locked. The account
+/// that last logged in is remembered, in a file when one is set, so logout
+/// and later runs use it.
+#[derive(Debug, Clone)]
+pub struct Pool<S: super::Trait> {
+    accounts: Vec<(String, S)>,
+    active: Arc<Mutex<usize>>,
+    state_file: Option<PathBuf>,
+    limiters: Option<Rc<Vec<Limiter>>>
+}
+
+impl<S: super::Trait> Pool<S> {
+
+    /// `accounts` pairs each username with its session, in the order they
+    /// are tried. It must hold at least one account.
+    pub fn new(accounts: Vec<(String, S)>) -> Self {
+        assert!(!accounts.is_empty(), "account pool is empty");
+        Self {
+            accounts,
+            active: Arc::new(Mutex::new(0)),
+            state_file: None,
+            limiters: None
+        }
+    }
+
+    /// Remembers the active account in `path`, starting from the account
+    /// saved there by a previous run.
+    pub fn with_state_file(mut self, path: &Path) -> Self {
+        let saved = std::fs::read_to_string(path).unwrap_or_default();
+        if let Some(index) = self.accounts.iter().position(|(username, _)| *username == saved.trim()) {
+            *self.active.lock().unwrap() = index;
+        }
+        self.state_file = Some(path.to_path_buf());
+        self
+    }
+
+    /// Limits the login attempts of each account with the limiter `limiter`
+    /// builds for its username, so one account getting locked doesn't stop
+    /// the others.
+    pub fn with_limiters(mut self, limiter: impl Fn(&str) -> Limiter) -> Self {
+        self.limiters = Some(Rc::new(self.accounts.iter().map(|(username, _)| limiter(username)).collect()));
+        self
+    }
+
+    fn limiter(&self, index: usize) -> Option<&Limiter> {
+        self.limiters.as_ref().map(|limiters| &limiters[index])
+    }
+
+    fn active(&self) -> usize {
+        *self.active.lock().unwrap()
+    }
+
+    fn set_active(&self, index: usize) {
+        *self.active.lock().unwrap() = index;
+        let Some(path) = &self.state_file else {
+            return;
+        };
+        let result = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(path, &self.accounts[index].0));
+        if let Err(error) = result {
+            log::warn(&format!("Failed to save active account to {}: {}", path.display(), error));
+        }
+    }
+
+    /// Errors tied to the account itself, which another account may not hit.
+    /// A session reported as already active only counts when it isn't ours,
+    /// i.e. the account is logged in on another device; ours is left for the
+    /// caller to settle.
+    fn fails_over(&self, index: usize, error: &Error) -> bool {
+        match error {
+            Error::CredentialsMismatch | Error::Locked(_) => true,
+            Error::AlreadyLoggedIn => !self.accounts[index].1.is_logged(),
+            _ => false
+        }
+    }
+
+    fn login_account(&self, index: usize) -> Result<(), Error> {
+        let session = &self.accounts[index].1;
+        let Some(limiter) = self.limiter(index) else {
+            return session.login();
+        };
+        limiter.check()?;
+        let result = session.login();
+        limiter.record(&result);
+        result
+    }
+
+}
+
+impl<S: super::Trait> super::Trait for Pool<S> {
+
+    fn is_logged(&self) -> bool {
+        self.accounts[self.active()].1.is_logged()
+    }
+
+    /// Tries each account once, starting with the active one.
+    fn login(&self) -> Result<(), Error> {
+        let start = self.active();
+        let count = self.accounts.len();
+        let mut last_error = None;
+        for index in (0..count).map(|offset| (start + offset) % count) {
+            let username = &self.accounts[index].0;
+            match self.login_account(index) {
+                Ok(()) => {
+                    if index != start {
+                        log::info(&format!("Logged in with account {}", username));
+                    }
+                    self.set_active(index);
+                    return Ok(());
+                }
+                Err(error) if count > 1 && self.fails_over(index, &error) => {
+                    log::warn(&format!("{} Account {} failed, trying the next one", error, username));
+                    last_error = Some(error);
+                }
+                Err(error) => {
+                    if error == Error::AlreadyLoggedIn {
+                        self.set_active(index);
+                    }
+                    return Err(error);
+                }
+            }
+        }
+        Err(last_error.unwrap_or(Error::CredentialsMismatch))
+    }
+
+    fn portal(&self) -> Option<url::Url> {
+        self.accounts[self.active()].1.portal()
+    }
+
+    fn account(&self) -> Option<String> {
+        Some(self.accounts[self.active()].0.clone())
+    }
+
+    fn limits(&self) -> Option<String> {
+        let limiters = self.limiters.as_ref()?;
+        let limits = self.accounts
+            .iter()
+            .zip(limiters.iter())
+            .map(|((username, _), limiter)| format!("{} {}", username, limiter))
+            .collect::<Vec<_>>();
+        Some(limits.join(", "))
+    }
+
+    fn logout(&self) -> Result<(), Error> {
+        self.accounts[self.active()].1.logout()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Fake, Trait};
+    use crate::testing::{Event, Journal};
+
+    fn pool(journal: &Journal, count: usize) -> (Pool<Fake>, Vec<Fake>) {
+        let sessions = (0..count).map(|_| Fake::new(journal)).collect::<Vec<_>>();
+        let accounts = sessions
+            .iter()
+            .enumerate()
+            .map(|(index, session)| (format!("user{}@meo.pt", index), session.clone()))
+            .collect();
+        (Pool::new(accounts), sessions)
+    }
+
+    #[test]
+    fn moves_to_next_account_and_logs_out_of_it() {
+        let journal = Journal::new();
+        let (pool, sessions) = pool(&journal, 3);
+        sessions[0].fail_login(Error::CredentialsMismatch);
+        sessions[1].fail_login(Error::CredentialsMismatch);
+
+        assert_eq!(pool.login(), Ok(()));
+        assert!(sessions[2].is_logged());
+
+        assert_eq!(pool.logout(), Ok(()));
+        assert!(!sessions[2].is_logged());
+    }
+
+    #[test]
+    fn stops_on_errors_unrelated_to_the_account() {
+        let journal = Journal::new();
+        let (pool, sessions) = pool(&journal, 2);
+        sessions[0].fail_login(Error::NetworkUnreachable);
+
+        assert_eq!(pool.login(), Err(Error::NetworkUnreachable));
+        assert!(!sessions[1].is_logged());
+    }
+
+    #[test]
+    fn moves_on_from_account_logged_in_elsewhere() {
+        let journal = Journal::new();
+        let (pool, sessions) = pool(&journal, 3);
+        sessions[0].fail_login(Error::AlreadyLoggedIn);
+
+        assert_eq!(pool.login(), Ok(()));
+        assert_eq!(pool.account().as_deref(), Some("user1@meo.pt"));
+        assert!(sessions[1].is_logged());
+        assert!(!sessions[2].is_logged());
+        assert_eq!(journal.events(), vec![Event::Login, Event::Login]);
+    }
+
+    #[test]
+    fn reports_last_error_when_every_account_fails() {
+        let journal = Journal::new();
+        let (pool, sessions) = pool(&journal, 2);
+        sessions[0].fail_login(Error::CredentialsMismatch);
+        sessions[1].fail_login(Error::CredentialsMismatch);
+
+        assert_eq!(pool.login(), Err(Error::CredentialsMismatch));
+    }
+
+    #[test]
+    fn limits_each_account_on_its_own() {
+        let journal = Journal::new();
+        let (pool, sessions) = pool(&journal, 2);
+        let pool = pool.with_limiters(|_| Limiter::default().with_max_mismatches(1));
+        sessions[0].fail_login(Error::CredentialsMismatch);
+
+        assert_eq!(pool.login(), Ok(()));
+        assert_eq!(pool.logout(), Ok(()));
+        assert_eq!(pool.login(), Ok(()));
+        assert_eq!(journal.events(), vec![Event::Login, Event::Login, Event::Logout, Event::Login]);
+        assert_eq!(
+            pool.limits().as_deref(),
+            Some("user0@meo.pt locked after 1 rejected logins, user1@meo.pt ok")
+        );
+
+        sessions[1].fail_login(Error::CredentialsMismatch);
+        assert_eq!(pool.login(), Err(Error::Locked(1)));
+        assert_eq!(journal.events().len(), 5);
+    }
+
+    #[test]
+    fn remembers_active_account_across_runs() {
+        let path = std::env::temp_dir().join(format!("meo-wifi-pool-{}", std::process::id()));
+        let journal = Journal::new();
+        let (first, sessions) = pool(&journal, 2);
+        sessions[0].fail_login(Error::CredentialsMismatch);
+        first.with_state_file(&path).login().unwrap();
+
+        let (second, sessions) = pool(&journal, 2);
+        sessions[1].set_logged(true);
+        let second = second.with_state_file(&path);
+        let _ = std::fs::remove_file(&path);
+
+        assert!(second.is_logged());
+        assert_eq!(second.logout(), Ok(()));
+        assert!(!sessions[1].is_logged());
+    }
+}
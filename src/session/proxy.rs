@@ -0,0 +1,79 @@
+use super::error::Error;
+
+/// Proxy that portal requests go through, along with the hosts that must be
+/// reached directly. Entries in the bypass list match the host itself and
+/// all of its subdomains, and `*` bypasses the proxy altogether.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Proxy {
+    url: Option<String>,
+    bypass: Vec<String>
+}
+
+impl Proxy {
+
+    /// Reads the conventional `HTTPS_PROXY`, `HTTP_PROXY` and `ALL_PROXY`
+    /// variables, in that order and in either case, plus `NO_PROXY`.
+    /// Unusable proxy addresses are ignored.
+    pub fn from_env() -> Self {
+        const VARIABLES: [&str; 6] = ["HTTPS_PROXY", "https_proxy", "HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"];
+        let url = VARIABLES
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|url| !url.is_empty() && ureq::Proxy::new(url).is_ok());
+        let bypass = std::env::var("NO_PROXY")
+            .or_else(|_| std::env::var("no_proxy"))
+            .unwrap_or_default();
+        Self { url, bypass: Vec::new() }.with_bypass(&bypass)
+    }
+
+    pub fn with_url(mut self, url: &str) -> Result<Self, Error> {
+        ureq::Proxy::new(url).map_err(|e| Error::Proxy(format!("{}: {}", url, e)))?;
+        self.url = Some(url.to_string());
+        Ok(self)
+    }
+
+    /// Adds hosts from a comma separated list in the `NO_PROXY` format.
+    pub fn with_bypass(mut self, hosts: &str) -> Self {
+        self.bypass.extend(
+            hosts
+                .split(',')
+                .map(|host| host.trim().trim_start_matches("*.").trim_start_matches('.').to_lowercase())
+                .filter(|host| !host.is_empty())
+        );
+        self
+    }
+
+    pub(super) fn bypasses(&self, host: &str) -> bool {
+        let host = host.to_lowercase();
+        self.bypass.iter().any(|entry| {
+            entry == "*" || host == *entry || host.ends_with(&format!(".{}", entry))
+        })
+    }
+
+    pub(super) fn url(&self) -> Option<ureq::Proxy> {
+        self.url.as_ref().map(|url| ureq::Proxy::new(url).expect("proxy URL validated on creation"))
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bypass_matches_host_and_subdomains() {
+        let proxy = Proxy::default().with_bypass("meo.pt, .example.com,*.fon.com");
+
+        assert!(proxy.bypasses("meo.pt"));
+        assert!(proxy.bypasses("servicoswifi.apps.MEO.pt"));
+        assert!(proxy.bypasses("www.example.com"));
+        assert!(proxy.bypasses("fon.com"));
+        assert!(!proxy.bypasses("notmeo.pt"));
+        assert!(Proxy::default().with_bypass("*").bypasses("anything.org"));
+    }
+
+    #[test]
+    fn rejects_unsupported_proxy_url() {
+        assert!(matches!(Proxy::default().with_url("ftp://proxy:21"), Err(Error::Proxy(_))));
+    }
+}
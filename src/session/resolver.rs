@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Where host names missing from the static map are looked up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Nameserver {
+    /// The system resolver, i.e. whatever DHCP configured.
+    #[default]
+    System,
+    /// The default gateway, queried directly on port 53. Hotspot gateways
+    /// usually answer even when the advertised resolvers don't.
+    Gateway,
+    Server(SocketAddr)
+}
+
+impl std::str::FromStr for Nameserver {
+    type Err = String;
+
+    /// Accepts `system`, `gateway` or a server address, with or without port.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        const DNS_PORT: u16 = 53;
+        match value {
+            "system" => Ok(Self::System),
+            "gateway" => Ok(Self::Gateway),
+            address => address
+                .parse::<SocketAddr>()
+                .or_else(|_| address.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, DNS_PORT)))
+                .map(Self::Server)
+                .map_err(|_| format!("Invalid DNS server: {}", address))
+        }
+    }
+}
+
+/// Resolves portal host names for the session backends, checking a static
+/// host map before asking the configured nameserver.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Resolver {
+    hosts: HashMap<String, IpAddr>,
+    nameserver: Nameserver
+}
+
+impl Resolver {
+
+    const TIMEOUT: Duration = Duration::from_secs(3);
+
+    pub fn with_nameserver(mut self, nameserver: Nameserver) -> Self {
+        self.nameserver = nameserver;
+        self
+    }
+
+    /// Adds host overrides from a comma separated list of `name=address`.
+    pub fn with_hosts(mut self, hosts: &str) -> Result<Self, String> {
+        for entry in hosts.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (name, address) = entry
+                .split_once('=')
+                .and_then(|(name, address)| Some((name.trim(), address.trim().parse::<IpAddr>().ok()?)))
+                .ok_or_else(|| format!("Invalid host override: {}", entry))?;
+            self.hosts.insert(name.to_lowercase(), address);
+        }
+        Ok(self)
+    }
+
+    fn lookup(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        if let Some(address) = self.hosts.get(&host.to_lowercase()) {
+            return Ok(vec![*address]);
+        }
+        match self.nameserver {
+            Nameserver::System => Ok((host, 0).to_socket_addrs()?.map(|address| address.ip()).collect()),
+            Nameserver::Gateway => query(SocketAddr::new(gateway()?, 53), host, Self::TIMEOUT),
+            Nameserver::Server(server) => query(server, host, Self::TIMEOUT)
+        }
+    }
+
+}
+
+impl ureq::Resolver for Resolver {
+    fn resolve(&self, netloc: &str) -> io::Result<Vec<SocketAddr>> {
+        let (host, port) = netloc
+            .rsplit_once(':')
+            .and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?)))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, netloc.to_string()))?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if let Ok(address) = host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(address, port)]);
+        }
+        Ok(self.lookup(host)?.into_iter().map(|address| SocketAddr::new(address, port)).collect())
+    }
+}
+
+/// Reads the default route's gateway from the kernel routing table.
+fn gateway() -> io::Result<IpAddr> {
+    let table = std::fs::read_to_string("/proc/net/route")?;
+    default_gateway(&table).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no default gateway"))
+}
+
+fn default_gateway(table: &str) -> Option<IpAddr> {
+    table.lines().skip(1).find_map(|line| {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if fields.get(1) != Some(&"00000000") {
+            return None;
+        }
+        let gateway = u32::from_str_radix(fields.get(2)?, 16).ok()?;
+        Some(IpAddr::V4(Ipv4Addr::from(gateway.to_le_bytes())))
+    })
+}
+
+/// Asks `server` for the IPv4 addresses of `host` with a single UDP query.
+/// Only a reply from `server` with the random query ID and the same
+/// question is taken, and all of it has to arrive within `timeout`.
+fn query(server: SocketAddr, host: &str, timeout: Duration) -> io::Result<Vec<IpAddr>> {
+    let deadline = Instant::now() + timeout;
+    let mut id = [0u8; 2];
+    SystemRandom::new()
+        .fill(&mut id)
+        .map_err(|_| io::Error::other("no randomness for the DNS query ID"))?;
+    let request = request(u16::from_be_bytes(id), host)?;
+    let local = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let socket = UdpSocket::bind(local)?;
+    socket.connect(server)?;
+    socket.send(&request)?;
+
+    let mut buffer = [0u8; 512];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "DNS query timed out"));
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let (length, from) = socket.recv_from(&mut buffer)?;
+        if from == server && answers_request(&buffer[..length], &request) {
+            return answers(&buffer[..length]);
+        }
+    }
+}
+
+/// Whether `response` is a reply carrying the ID and question of `request`.
+fn answers_request(response: &[u8], request: &[u8]) -> bool {
+    const QUERY_RESPONSE: u8 = 0x80;
+    response.len() >= request.len()
+        && response[..2] == request[..2]
+        && response[2] & QUERY_RESPONSE != 0
+        && response[4..6] == request[4..6]
+        && response[12..request.len()].eq_ignore_ascii_case(&request[12..])
+}
+
+fn request(id: u16, host: &str) -> io::Result<Vec<u8>> {
+    const RECURSION_DESIRED: [u8; 2] = [0x01, 0x00];
+    const ONE_QUESTION: [u8; 8] = [0, 1, 0, 0, 0, 0, 0, 0];
+    const TYPE_A_CLASS_IN: [u8; 4] = [0, 1, 0, 1];
+    let mut packet = Vec::with_capacity(512);
+    packet.extend(id.to_be_bytes());
+    packet.extend(RECURSION_DESIRED);
+    packet.extend(ONE_QUESTION);
+    for label in host.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid host name {}", host)));
+        }
+        packet.push(label.len() as u8);
+        packet.extend(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend(TYPE_A_CLASS_IN);
+    Ok(packet)
+}
+
+fn answers(response: &[u8]) -> io::Result<Vec<IpAddr>> {
+    const NAME_ERROR: u8 = 3;
+    const TYPE_A: u16 = 1;
+    let truncated = || io::Error::new(io::ErrorKind::InvalidData, "truncated DNS response");
+    let u16_at = |position: usize| {
+        response.get(position..position + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]])).ok_or_else(truncated)
+    };
+
+    match response.get(3).ok_or_else(truncated)? & 0x0f {
+        0 => {}
+        NAME_ERROR => return Err(io::Error::new(io::ErrorKind::NotFound, "no such host")),
+        code => return Err(io::Error::other(format!("DNS server answered with code {}", code)))
+    }
+
+    let mut position = 12;
+    for _ in 0..u16_at(4)? {
+        position = skip_name(response, position).ok_or_else(truncated)? + 4;
+    }
+
+    let mut addresses = Vec::new();
+    for _ in 0..u16_at(6)? {
+        position = skip_name(response, position).ok_or_else(truncated)?;
+        let record_type = u16_at(position)?;
+        let length = u16_at(position + 8)? as usize;
+        let data = response.get(position + 10..position + 10 + length).ok_or_else(truncated)?;
+        if record_type == TYPE_A && length == 4 {
+            addresses.push(IpAddr::V4(Ipv4Addr::new(data[0], data[1], data[2], data[3])));
+        }
+        position += 10 + length;
+    }
+
+    if addresses.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "no address records"));
+    }
+    Ok(addresses)
+}
+
+/// Returns the position right after the (possibly compressed) name at `position`.
+fn skip_name(message: &[u8], mut position: usize) -> Option<usize> {
+    loop {
+        let length = *message.get(position)? as usize;
+        match length {
+            0 => return Some(position + 1),
+            _ if length & 0xc0 == 0xc0 => return Some(position + 2),
+            _ => position += length + 1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ureq::Resolver as _;
+
+    const NAME_ERROR_CODE: u8 = 3;
+
+    fn server(reply: impl Fn(&[u8]) -> Vec<u8> + Send + 'static) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            while let Ok((length, from)) = socket.recv_from(&mut buffer) {
+                let _ = socket.send_to(&reply(&buffer[..length]), from);
+            }
+        });
+        address
+    }
+
+    fn answer(query: &[u8], rcode: u8, address: [u8; 4]) -> Vec<u8> {
+        let mut reply = query.to_vec();
+        reply[2] = 0x81;
+        reply[3] = 0x80 | rcode;
+        reply[7] = if rcode == 0 { 1 } else { 0 };
+        if rcode == 0 {
+            reply.extend([0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+            reply.extend(address);
+        }
+        reply
+    }
+
+    #[test]
+    fn static_hosts_win_over_nameserver() {
+        let resolver = Resolver::default()
+            .with_nameserver(Nameserver::Server("127.0.0.1:9".parse().unwrap()))
+            .with_hosts("servicoswifi.apps.meo.pt=192.0.2.10")
+            .unwrap();
+
+        assert_eq!(
+            resolver.resolve("ServicosWifi.apps.meo.pt:443").unwrap(),
+            vec!["192.0.2.10:443".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn queries_configured_server() {
+        let address = server(|query| answer(query, 0, [198, 51, 100, 7]));
+        let resolver = Resolver::default().with_nameserver(Nameserver::Server(address));
+
+        assert_eq!(
+            resolver.resolve("portal.example:80").unwrap(),
+            vec!["198.51.100.7:80".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn ignores_replies_to_another_question() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            if let Ok((length, from)) = socket.recv_from(&mut buffer) {
+                let query = &buffer[..length];
+                let mut spoofed = query.to_vec();
+                spoofed[13] = b'x';
+                let _ = socket.send_to(&answer(&spoofed, 0, [203, 0, 113, 66]), from);
+                let _ = socket.send_to(&answer(query, 0, [198, 51, 100, 7]), from);
+            }
+        });
+
+        assert_eq!(query(address, "portal.example", Resolver::TIMEOUT).unwrap(), vec![IpAddr::from([198, 51, 100, 7])]);
+    }
+
+    #[test]
+    fn stream_of_mismatched_replies_does_not_extend_timeout() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            let Ok((length, from)) = socket.recv_from(&mut buffer) else {
+                return;
+            };
+            let mut reply = answer(&buffer[..length], 0, [203, 0, 113, 66]);
+            reply[0] ^= 0xff;
+            for _ in 0..40 {
+                let _ = socket.send_to(&reply, from);
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        });
+
+        let started = Instant::now();
+        let error = query(address, "portal.example", Duration::from_millis(300)).unwrap_err();
+
+        assert!(matches!(error.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn unknown_host_is_not_found() {
+        let address = server(|query| answer(query, NAME_ERROR_CODE, [0; 4]));
+        let resolver = Resolver::default().with_nameserver(Nameserver::Server(address));
+
+        assert_eq!(resolver.resolve("missing.example:80").unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn reads_default_gateway_from_route_table() {
+        let table = "Iface\tDestination\tGateway\tFlags\n\
+                     wlan0\t0000A8C0\t00000000\t0001\n\
+                     wlan0\t00000000\t0101A8C0\t0003\n";
+
+        assert_eq!(default_gateway(table), Some(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1))));
+    }
+
+    #[test]
+    fn parses_nameserver_settings() {
+        assert_eq!("gateway".parse(), Ok(Nameserver::Gateway));
+        assert_eq!("1.1.1.1".parse(), Ok(Nameserver::Server("1.1.1.1:53".parse().unwrap())));
+        assert_eq!("[::1]:5353".parse(), Ok(Nameserver::Server("[::1]:5353".parse().unwrap())));
+        assert!("dns.example".parse::<Nameserver>().is_err());
+    }
+}
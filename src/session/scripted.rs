@@ -0,0 +1,436 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use lazy_regex::{regex, Regex};
+use serde::Deserialize;
+use url::Url;
+use super::error::Error;
+use super::form::Form;
+use super::legacy::Legacy;
+use super::transport::{self, Transport};
+use super::validation::Validation;
+
+/// Single HTTP request of a flow. Templates in `url` and `form` may use
+/// `{{variable}}` or `{{variable|filter|...}}`; `extract` stores values found
+/// in the response body as new variables for the following steps.
+#[derive(Debug, Clone, Deserialize)]
+struct Step {
+    #[serde(default = "Step::default_method")]
+    method: String,
+    url: String,
+    #[serde(default)]
+    form: BTreeMap<String, String>,
+    #[serde(default)]
+    extract: BTreeMap<String, Extraction>
+}
+
+impl Step {
+    fn default_method() -> String {
+        "GET".to_string()
+    }
+}
+
+/// Where an extracted value comes from: the first capture group of a regex,
+/// a hidden or ticked field of the login form on the page, or an attribute
+/// of that form.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum Extraction {
+    Pattern(String),
+    Field { field: String },
+    Form { form: String }
+}
+
+impl Extraction {
+    const FORM_ATTRIBUTES: [&str; 3] = ["action", "username_field", "password_field"];
+
+    fn find(&self, body: &str, page: &Url) -> Result<Option<String>, Error> {
+        let form = || Form::parse(body, page);
+        Ok(match self {
+            Extraction::Pattern(pattern) => Scripted::compile(pattern)?
+                .captures(body)
+                .and_then(|c| c.get(1).or_else(|| c.get(0)))
+                .map(|value| value.as_str().to_string()),
+            Extraction::Field { field } => form().and_then(|form| {
+                form.fields
+                    .into_iter()
+                    .find(|(name, _)| name == field)
+                    .map(|(_, value)| value)
+            }),
+            Extraction::Form { form: attribute } => form().and_then(|form| match attribute.as_str() {
+                "action" => Some(form.action.to_string()),
+                "username_field" => Some(form.username_field),
+                "password_field" => Some(form.password_field),
+                _ => None
+            })
+        })
+    }
+}
+
+/// Maps a regex matched against the last response body to an error.
+#[derive(Debug, Clone, Deserialize)]
+struct Rule {
+    #[serde(rename = "match")]
+    pattern: String,
+    error: String
+}
+
+/// Sequence of steps whose last response is checked against `errors`, in
+/// order, and then against `success`. A flow without `success` succeeds
+/// whenever all its requests do.
+#[derive(Debug, Clone, Deserialize)]
+struct Flow {
+    steps: Vec<Step>,
+    #[serde(default)]
+    success: Option<String>,
+    #[serde(default)]
+    errors: Vec<Rule>
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Definition {
+    name: String,
+    #[serde(default)]
+    status: Option<Flow>,
+    login: Flow,
+    #[serde(default)]
+    logout: Option<Flow>
+}
+
+/// Session driven by a declarative portal definition, so new captive
+/// portals can be supported without recompiling. See `portals/` for examples.
+#[derive(Debug, Clone)]
+pub struct Scripted {
+    definition: Definition,
+    variables: HashMap<String, String>,
+    agent: transport::Agent
+}
+
+impl Scripted {
+
+    const FILTERS: [&str; 2] = ["urlencode", "meo_encrypt"];
+
+    pub fn new(username: &str, password: &str, validation: Validation, definition: &str) -> Result<Self, Error> {
+        validation.check(username, password)?;
+        let definition: Definition = serde_json::from_str(definition)
+            .map_err(|e| Error::Definition(e.to_string()))?;
+        Self::validate(&definition)?;
+        Ok(Self {
+            definition,
+            variables: HashMap::from([
+                ("username".to_string(), username.to_string()),
+                ("password".to_string(), password.to_string()),
+            ]),
+            agent: Self::agent(&Transport::default())
+        })
+    }
+
+    pub fn with_transport(mut self, transport: &Transport) -> Self {
+        self.agent = Self::agent(transport);
+        self
+    }
+
+    fn agent(transport: &Transport) -> transport::Agent {
+        const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+        transport.agent(|| {
+            ureq::AgentBuilder::new()
+                .timeout(TIMEOUT)
+                .user_agent("Mozilla/5.0 (X11; Linux x86_64)")
+        })
+    }
+
+    pub fn from_file(username: &str, password: &str, validation: Validation, path: &Path) -> Result<Self, Error> {
+        let definition = std::fs::read_to_string(path)
+            .map_err(|e| Error::Definition(format!("{}: {}", path.display(), e)))?;
+        Self::new(username, password, validation, &definition)
+    }
+
+    /// Sets a variable available to every flow, e.g. `local_ip`, which is
+    /// otherwise detected when first used.
+    #[allow(dead_code)]
+    pub fn with_variable(mut self, name: &str, value: &str) -> Self {
+        self.variables.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    fn compile(pattern: &str) -> Result<Regex, Error> {
+        Regex::new(pattern).map_err(|e| Error::Definition(e.to_string()))
+    }
+
+    fn error(name: &str) -> Option<Error> {
+        match name {
+            "credentials_mismatch" => Some(Error::CredentialsMismatch),
+            "invalid_ip" => Some(Error::InvalidIp),
+            "network_unreachable" => Some(Error::NetworkUnreachable),
+            "already_logged_in" => Some(Error::AlreadyLoggedIn),
+            _ => None
+        }
+    }
+
+    /// Catches mistakes in the definition at load time rather than when the
+    /// flow first runs.
+    fn validate(definition: &Definition) -> Result<(), Error> {
+        let flows = [Some(&definition.login), definition.status.as_ref(), definition.logout.as_ref()];
+        for flow in flows.into_iter().flatten() {
+            if let Some(success) = &flow.success {
+                Self::compile(success)?;
+            }
+            for rule in &flow.errors {
+                Self::compile(&rule.pattern)?;
+                Self::error(&rule.error)
+                    .ok_or_else(|| Error::Definition(format!("unknown error: {}", rule.error)))?;
+            }
+            for step in &flow.steps {
+                for extraction in step.extract.values() {
+                    match extraction {
+                        Extraction::Pattern(pattern) => {
+                            Self::compile(pattern)?;
+                        }
+                        Extraction::Form { form } if !Extraction::FORM_ATTRIBUTES.contains(&form.as_str()) => {
+                            return Err(Error::Definition(format!("unknown form attribute: {}", form)));
+                        }
+                        _ => {}
+                    }
+                }
+                let templates = std::iter::once(&step.url).chain(step.form.values());
+                for template in templates {
+                    for filter in regex!(r"\|\s*([A-Za-z0-9_]+)").captures_iter(template) {
+                        if !Self::FILTERS.contains(&&filter[1]) {
+                            return Err(Error::Definition(format!("unknown filter: {}", &filter[1])));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn variable(&self, name: &str, variables: &mut HashMap<String, String>) -> Result<String, Error> {
+        if let Some(value) = variables.get(name) {
+            return Ok(value.clone());
+        }
+        if name == "local_ip" {
+            let ip = Legacy::get_local_ip().ok_or(Error::NetworkUnreachable)?;
+            variables.insert(name.to_string(), ip.clone());
+            return Ok(ip);
+        }
+        Err(Error::Definition(format!("unknown variable: {}", name)))
+    }
+
+    fn render(&self, template: &str, variables: &mut HashMap<String, String>) -> Result<String, Error> {
+        let pattern = regex!(r"\{\{\s*([A-Za-z0-9_]+)((?:\s*\|\s*[A-Za-z0-9_]+)*)\s*\}\}");
+        let mut rendered = String::new();
+        let mut last = 0;
+        for placeholder in pattern.captures_iter(template) {
+            let whole = placeholder.get(0).unwrap();
+            let mut value = self.variable(&placeholder[1], variables)?;
+            for filter in placeholder[2].split('|').map(str::trim).filter(|f| !f.is_empty()) {
+                value = match filter {
+                    "urlencode" => urlencoding::encode(&value).into_owned(),
+                    "meo_encrypt" => Legacy::encrypt_password(&value, &self.variable("local_ip", variables)?)?,
+                    _ => return Err(Error::Definition(format!("unknown filter: {}", filter)))
+                };
+            }
+            rendered.push_str(&template[last..whole.start()]);
+            rendered.push_str(&value);
+            last = whole.end();
+        }
+        rendered.push_str(&template[last..]);
+        Ok(rendered)
+    }
+
+    /// Sends the step and returns the address of the page reached, after
+    /// redirects, along with its body.
+    fn send(&self, step: &Step, variables: &mut HashMap<String, String>) -> Result<(Url, String), Error> {
+        let url = self.render(&step.url, variables)?;
+        let request = self.agent.request(&step.method.to_uppercase(), &url);
+        let response = if step.form.is_empty() {
+            request.call()?
+        } else {
+            let form = step.form
+                .iter()
+                .map(|(name, value)| Ok((name.as_str(), self.render(value, variables)?)))
+                .collect::<Result<Vec<_>, Error>>()?;
+            let form: Vec<(&str, &str)> = form.iter().map(|(n, v)| (*n, v.as_str())).collect();
+            request.send_form(&form)?
+        };
+        let page = Url::parse(response.get_url())
+            .map_err(|e| Error::Portal(format!("{}: invalid address {}: {}", self.definition.name, response.get_url(), e)))?;
+        Ok((page, response.into_string()?))
+    }
+
+    fn run(&self, flow: &Flow) -> Result<bool, Error> {
+        let mut variables = self.variables.clone();
+        let mut body = String::new();
+        for step in &flow.steps {
+            let page;
+            (page, body) = self.send(step, &mut variables)?;
+            for (name, extraction) in &step.extract {
+                let value = extraction
+                    .find(&body, &page)?
+                    .ok_or_else(|| Error::Portal(format!("{}: could not find {}", self.definition.name, name)))?;
+                variables.insert(name.clone(), value);
+            }
+        }
+
+        for rule in &flow.errors {
+            if Self::compile(&rule.pattern)?.is_match(&body) {
+                return Err(Self::error(&rule.error).unwrap_or(Error::Portal(rule.error.clone())));
+            }
+        }
+
+        match &flow.success {
+            Some(success) => Ok(Self::compile(success)?.is_match(&body)),
+            None => Ok(true)
+        }
+    }
+
+}
+
+impl super::Trait for Scripted {
+
+    fn is_logged(&self) -> bool {
+        self.definition
+            .status
+            .as_ref()
+            .is_some_and(|status| self.run(status).unwrap_or(false))
+    }
+
+    fn login(&self) -> Result<(), Error> {
+        match self.run(&self.definition.login)? {
+            true => Ok(()),
+            false => Err(Error::Portal(format!("{}: login failed", self.definition.name)))
+        }
+    }
+
+    fn logout(&self) -> Result<(), Error> {
+        match &self.definition.logout {
+            Some(logout) if !self.run(logout)? => {
+                Err(Error::Portal(format!("{}: failed to logout", self.definition.name)))
+            }
+            _ => Ok(())
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Trait;
+    use crate::testing::{Response, Server};
+
+    const MEO_LEGACY: &str = include_str!("../../portals/meo-legacy.json");
+
+    fn meo_legacy(server: &Server) -> Scripted {
+        let definition = MEO_LEGACY.replace("https://servicoswifi.apps.meo.pt", &server.url(""));
+        Scripted::new("user@example.pt", "Password1", Validation::Strict, &definition)
+            .unwrap()
+            .with_variable("local_ip", "10.0.0.1")
+    }
+
+    #[test]
+    fn meo_legacy_definition_logs_in_with_encrypted_password() {
+        let server = Server::start(|_| Response::ok(r#"{"result":true}"#));
+
+        assert_eq!(meo_legacy(&server).login(), Ok(()));
+        assert_eq!(
+            server.requests()[0].path,
+            "/HotspotConnection.svc/Login?username=user%40example.pt&password=X1wdkXENVPs9kNcl3KKJMQ%3D%3D&navigatorLang=en&callback="
+        );
+    }
+
+    #[test]
+    fn meo_legacy_definition_maps_portal_errors() {
+        let server = Server::start(|_| Response::ok(r#"{"result":false,"error":"Invalid Credentials"}"#));
+
+        assert_eq!(meo_legacy(&server).login(), Err(Error::CredentialsMismatch));
+    }
+
+    #[test]
+    fn meo_legacy_definition_reads_state_and_logs_out() {
+        let server = Server::start(|request| match request.path.as_str() {
+            p if p.contains("GetState") => Response::ok(r#"{"LoggedOn": true}"#),
+            _ => Response::ok("true")
+        });
+        let session = meo_legacy(&server);
+
+        assert!(session.is_logged());
+        assert_eq!(session.logout(), Ok(()));
+    }
+
+    #[test]
+    fn extracts_values_for_later_steps() {
+        let server = Server::start(|request| match request.method.as_str() {
+            "GET" => Response::ok(r#"<input name="token" value="abc123">"#),
+            _ => Response::ok("Welcome")
+        });
+        let definition = format!(r#"{{
+            "name": "hotel",
+            "login": {{
+                "steps": [
+                    {{ "url": "{}", "extract": {{ "token": "name=\"token\" value=\"([^\"]+)\"" }} }},
+                    {{ "method": "POST", "url": "{}", "form": {{ "token": "{{{{token}}}}", "room": "{{{{username}}}}" }} }}
+                ],
+                "success": "Welcome"
+            }}
+        }}"#, server.url("/"), server.url("/login"));
+        let session = Scripted::new("412", "", Validation::Off, &definition).unwrap();
+
+        assert_eq!(session.login(), Ok(()));
+        assert_eq!(server.requests()[1].body, "room=412&token=abc123");
+    }
+
+    #[test]
+    fn extracts_login_form_fields_for_later_steps() {
+        let server = Server::start(|request| match request.method.as_str() {
+            "GET" => Response::ok(r#"
+                <form method="post" action="/guest/login">
+                    <input type="hidden" name="_csrf" value="t0k&amp;en">
+                    <input type="text" name="room">
+                    <input type="password" name="surname">
+                </form>
+            "#),
+            _ => Response::ok("Welcome")
+        });
+        let definition = format!(r#"{{
+            "name": "hotel",
+            "login": {{
+                "steps": [
+                    {{ "url": "{}", "extract": {{ "csrf": {{ "field": "_csrf" }}, "target": {{ "form": "action" }} }} }},
+                    {{ "method": "POST", "url": "{{{{target}}}}", "form": {{ "_csrf": "{{{{csrf}}}}", "room": "{{{{username}}}}" }} }}
+                ],
+                "success": "Welcome"
+            }}
+        }}"#, server.url("/start"));
+        let session = Scripted::new("412", "", Validation::Off, &definition).unwrap();
+
+        assert_eq!(session.login(), Ok(()));
+        assert_eq!(server.requests()[1].path, "/guest/login");
+        assert_eq!(server.requests()[1].body, "_csrf=t0k%26en&room=412");
+    }
+
+    #[test]
+    fn rejects_invalid_definitions() {
+        let definition = |filter: &str| format!(
+            r#"{{ "name": "x", "login": {{ "steps": [ {{ "url": "http://x/{{{{username|{}}}}}" }} ] }} }}"#,
+            filter
+        );
+
+        assert!(Scripted::new("u", "p", Validation::Off, &definition("urlencode")).is_ok());
+        assert_eq!(
+            Scripted::new("u", "p", Validation::Off, &definition("rot13")).err(),
+            Some(Error::Definition("unknown filter: rot13".to_string()))
+        );
+        assert_eq!(
+            Scripted::new("u", "p", Validation::Off, r#"{
+                "name": "x",
+                "login": { "steps": [ { "url": "http://x/", "extract": { "y": { "form": "method" } } } ] }
+            }"#).err(),
+            Some(Error::Definition("unknown form attribute: method".to_string()))
+        );
+        assert!(matches!(
+            Scripted::new("u", "p", Validation::Off, "{}"),
+            Err(Error::Definition(_))
+        ));
+    }
+}
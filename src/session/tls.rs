@@ -0,0 +1,304 @@
+use std::path::Path;
+use std::sync::Arc;
+use base64::prelude::{BASE64_STANDARD as base64, Engine};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::pki_types::pem::PemObject;
+use rustls::{CertificateError, DigitallySignedStruct, OtherError, RootCertStore, SignatureScheme};
+use super::error::Error;
+
+/// SHA-256 digest of a certificate's SubjectPublicKeyInfo, written
+/// `sha256/<base64>` as in HPKP. Pinning the key rather than the whole
+/// certificate survives renewals that keep the same key pair.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pin([u8; 32]);
+
+impl Pin {
+
+    fn of(certificate: &CertificateDer<'_>) -> Result<Self, rustls::Error> {
+        let spki = subject_public_key_info(certificate)
+            .ok_or(rustls::Error::InvalidCertificate(CertificateError::BadEncoding))?;
+        let digest = ring::digest::digest(&ring::digest::SHA256, spki);
+        Ok(Self(digest.as_ref().try_into().expect("SHA-256 digest is 32 bytes")))
+    }
+
+}
+
+impl std::str::FromStr for Pin {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        base64
+            .decode(value.trim().trim_start_matches("sha256/"))
+            .ok()
+            .and_then(|digest| digest.try_into().ok())
+            .map(Self)
+            .ok_or_else(|| Error::TlsConfig(format!("invalid pin {}", value)))
+    }
+}
+
+impl std::fmt::Display for Pin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sha256/{}", base64.encode(self.0))
+    }
+}
+
+/// Handshake failure raised when the portal key matches none of the pins.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch(pub Pin);
+
+impl std::error::Error for Mismatch {}
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "certificate key {} matches no pin", self.0)
+    }
+}
+
+/// Reads the PEM encoded CA certificates in `path`.
+pub fn trust_store(path: &Path) -> Result<RootCertStore, Error> {
+    let invalid = |e: &dyn std::fmt::Display| Error::TlsConfig(format!("{}: {}", path.display(), e));
+    let mut roots = RootCertStore::empty();
+    for certificate in CertificateDer::pem_file_iter(path).map_err(|e| invalid(&e))? {
+        roots.add(certificate.map_err(|e| invalid(&e))?).map_err(|e| invalid(&e))?;
+    }
+    if roots.is_empty() {
+        return Err(invalid(&"no certificates found"));
+    }
+    Ok(roots)
+}
+
+pub fn default_trust_store() -> RootCertStore {
+    RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() }
+}
+
+/// Client configuration trusting `roots` and, when `pins` is not empty,
+/// only accepting servers whose certificate key is pinned.
+pub fn client_config(roots: Arc<RootCertStore>, pins: &[Pin]) -> Arc<rustls::ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let verifier = WebPkiServerVerifier::builder_with_provider(roots, provider.clone())
+        .build()
+        .expect("trust store is not empty");
+    let config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .expect("ring supports the default protocol versions")
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(Verifier { inner: verifier, pins: pins.to_vec() }))
+        .with_no_client_auth();
+    Arc::new(config)
+}
+
+/// Checks the chain as usual, then the leaf key against the pins.
+#[derive(Debug)]
+struct Verifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<Pin>
+}
+
+impl ServerCertVerifier for Verifier {
+
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+        if self.pins.is_empty() {
+            return Ok(verified);
+        }
+        let pin = Pin::of(end_entity)?;
+        if self.pins.contains(&pin) {
+            return Ok(verified);
+        }
+        Err(rustls::Error::InvalidCertificate(CertificateError::Other(OtherError(Arc::new(Mismatch(pin))))))
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, certificate, signature)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, certificate, signature)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+
+}
+
+/// Finds the TLS failure behind a request error, if that is what it was.
+pub fn handshake_error<'a>(error: &'a (dyn std::error::Error + 'static)) -> Option<&'a rustls::Error> {
+    let mut source = Some(error);
+    while let Some(error) = source {
+        let inner = error
+            .downcast_ref::<std::io::Error>()
+            .and_then(|io| io.get_ref())
+            .map(|inner| inner as &(dyn std::error::Error + 'static))
+            .unwrap_or(error);
+        if let Some(tls) = inner.downcast_ref::<rustls::Error>() {
+            return Some(tls);
+        }
+        source = error.source();
+    }
+    None
+}
+
+/// The pin presented by the server when `error` is a pin mismatch.
+pub fn mismatch(error: &rustls::Error) -> Option<Pin> {
+    match error {
+        rustls::Error::InvalidCertificate(CertificateError::Other(OtherError(other))) => {
+            other.downcast_ref::<Mismatch>().map(|mismatch| mismatch.0)
+        }
+        _ => None
+    }
+}
+
+/// Slices the DER encoded SubjectPublicKeyInfo out of a certificate,
+/// tag and length included, walking just enough of the structure:
+/// `Certificate ::= SEQUENCE { tbsCertificate SEQUENCE { [0] version
+/// OPTIONAL, serialNumber, signature, issuer, validity, subject,
+/// subjectPublicKeyInfo, ... }, ... }`.
+fn subject_public_key_info<'a>(certificate: &'a CertificateDer<'_>) -> Option<&'a [u8]> {
+    const VERSION_TAG: u8 = 0xa0;
+    let (_, certificate, _) = der_element(certificate)?;
+    let (_, mut tbs, _) = der_element(certificate)?;
+    if tbs.first() == Some(&VERSION_TAG) {
+        tbs = der_element(tbs)?.2;
+    }
+    for _ in 0..5 {
+        tbs = der_element(tbs)?.2;
+    }
+    let (header, content, _) = der_element(tbs)?;
+    Some(&tbs[..header + content.len()])
+}
+
+/// Splits a DER element into its header length, content and the bytes after it.
+fn der_element(data: &[u8]) -> Option<(usize, &[u8], &[u8])> {
+    let first = *data.get(1)? as usize;
+    let (header, length) = match first {
+        0..=0x7f => (2, first),
+        0x81..=0x84 => {
+            let size = first & 0x7f;
+            let length = data.get(2..2 + size)?.iter().fold(0usize, |length, byte| length << 8 | *byte as usize);
+            (2 + size, length)
+        }
+        _ => return None
+    };
+    let content = data.get(header..header + length)?;
+    Some((header, content, &data[header + length..]))
+}
+
+#[cfg(test)]
+pub (super) mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    pub const CA: &str = include_str!("fixtures/tls/ca.pem");
+    const PORTAL: &str = include_str!("fixtures/tls/portal.pem");
+    const PORTAL_KEY: &str = include_str!("fixtures/tls/portal-key.pem");
+    pub const PORTAL_PIN: &str = "sha256/Y1HHai9u5y33pr5pMib/0D5wxNNqpS5p+A+/sIkcgVM=";
+
+    /// HTTPS server for `localhost` answering every request with `body`.
+    pub fn server(body: &'static str) -> String {
+        let certificates = CertificateDer::pem_slice_iter(PORTAL.as_bytes()).collect::<Result<Vec<_>, _>>().unwrap();
+        let key = rustls::pki_types::PrivateKeyDer::from_pem_slice(PORTAL_KEY.as_bytes()).unwrap();
+        let config = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(certificates, key)
+            .unwrap();
+        let config = Arc::new(config);
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let connection = rustls::ServerConnection::new(config.clone()).unwrap();
+                let mut tls = rustls::StreamOwned::new(connection, stream);
+                let mut request = [0u8; 1024];
+                if tls.read(&mut request).is_ok() {
+                    let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+                    let _ = tls.write_all(response.as_bytes());
+                    tls.conn.send_close_notify();
+                    let _ = tls.flush();
+                }
+            }
+        });
+        format!("https://localhost:{}", port)
+    }
+
+    pub fn test_roots() -> Arc<RootCertStore> {
+        let mut roots = RootCertStore::empty();
+        roots.add_parsable_certificates(CertificateDer::pem_slice_iter(CA.as_bytes()).flatten());
+        Arc::new(roots)
+    }
+
+    fn agent(pins: &[Pin]) -> ureq::Agent {
+        ureq::AgentBuilder::new().tls_config(client_config(test_roots(), pins)).build()
+    }
+
+    #[test]
+    fn computes_pin_of_certificate_key() {
+        let certificate = CertificateDer::from_pem_slice(PORTAL.as_bytes()).unwrap();
+
+        assert_eq!(Pin::of(&certificate).unwrap().to_string(), PORTAL_PIN);
+        assert_eq!(PORTAL_PIN.parse::<Pin>().unwrap().to_string(), PORTAL_PIN);
+        assert!("sha256/c2hvcnQ=".parse::<Pin>().is_err());
+    }
+
+    #[test]
+    fn accepts_pinned_portal() {
+        let url = server("ok");
+        let pins = [PORTAL_PIN.parse().unwrap()];
+
+        assert_eq!(agent(&pins).get(&url).call().unwrap().into_string().unwrap(), "ok");
+    }
+
+    #[test]
+    fn rejects_portal_with_other_key() {
+        let url = server("ok");
+        let pins = ["sha256/AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".parse().unwrap()];
+
+        let error = agent(&pins).get(&url).call().unwrap_err();
+        let tls = handshake_error(&error).expect("handshake failure");
+        assert_eq!(mismatch(tls), Some(PORTAL_PIN.parse().unwrap()));
+    }
+
+    #[test]
+    fn untrusted_certificate_is_not_a_pin_mismatch() {
+        let url = server("ok");
+        let agent = ureq::AgentBuilder::new()
+            .tls_config(client_config(Arc::new(default_trust_store()), &[]))
+            .build();
+
+        let error = agent.get(&url).call().unwrap_err();
+        let tls = handshake_error(&error).expect("handshake failure");
+        assert_eq!(mismatch(tls), None);
+    }
+
+    #[test]
+    fn loads_trust_store_from_pem_file() {
+        let path = std::env::temp_dir().join(format!("meo-wifi-ca-{}.pem", std::process::id()));
+        std::fs::write(&path, CA).unwrap();
+        let roots = trust_store(&path);
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(roots.map(|roots| roots.len()), Ok(1));
+        assert!(trust_store(Path::new("/nonexistent/ca.pem")).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use std::path::Path;
+use std::sync::Arc;
+use rustls::RootCertStore;
+use url::Url;
+use super::error::Error;
+use super::proxy::Proxy;
+use super::resolver::Resolver;
+use super::tls::{self, Pin};
+
+/// Network settings shared by the session backends: the proxy portal
+/// requests go through, how portal host names are resolved and which
+/// certificates are trusted.
+#[derive(Debug, Clone, Default)]
+pub struct Transport {
+    proxy: Proxy,
+    resolver: Resolver,
+    trust_store: Option<Arc<RootCertStore>>,
+    pins: Vec<Pin>
+}
+
+impl Transport {
+
+    pub fn with_proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    pub fn with_resolver(mut self, resolver: Resolver) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
+    /// Trusts only the CA certificates in the PEM file at `path` instead
+    /// of the bundled web roots.
+    pub fn with_trust_store(mut self, path: &Path) -> Result<Self, Error> {
+        self.trust_store = Some(Arc::new(tls::trust_store(path)?));
+        Ok(self)
+    }
+
+    /// Only accepts servers whose certificate key matches one of `pins`.
+    pub fn with_pins(mut self, pins: &[Pin]) -> Self {
+        self.pins = pins.to_vec();
+        self
+    }
+
+    fn tls_config(&self) -> Option<Arc<rustls::ClientConfig>> {
+        if self.trust_store.is_none() && self.pins.is_empty() {
+            return None;
+        }
+        let roots = self.trust_store.clone().unwrap_or_else(|| Arc::new(tls::default_trust_store()));
+        Some(tls::client_config(roots, &self.pins))
+    }
+
+    /// Builds the agents used by a session backend. `builder` is called once
+    /// for direct requests and once more when a proxy is configured.
+    pub fn agent(&self, builder: impl Fn() -> ureq::AgentBuilder) -> Agent {
+        let tls_config = self.tls_config();
+        let builder = || match &tls_config {
+            Some(config) => builder().tls_config(config.clone()),
+            None => builder()
+        };
+        let proxied = self.proxy.url().map(|proxy| {
+            builder().resolver(self.resolver.clone()).proxy(proxy).build()
+        });
+        Agent {
+            direct: builder().resolver(self.resolver.clone()).try_proxy_from_env(false).build(),
+            proxied,
+            proxy: self.proxy.clone()
+        }
+    }
+
+}
+
+/// Sends each request either directly or through the proxy, depending on
+/// the host it is addressed to. Cookies are kept per agent, which is fine
+/// as a given host is always reached the same way.
+#[derive(Debug, Clone)]
+pub struct Agent {
+    direct: ureq::Agent,
+    proxied: Option<ureq::Agent>,
+    proxy: Proxy
+}
+
+impl Agent {
+
+    pub fn for_url(&self, url: &Url) -> &ureq::Agent {
+        match &self.proxied {
+            Some(proxied) if !url.host_str().is_some_and(|host| self.proxy.bypasses(host)) => proxied,
+            _ => &self.direct
+        }
+    }
+
+    pub fn request_url(&self, method: &str, url: &Url) -> ureq::Request {
+        self.for_url(url).request_url(method, url)
+    }
+
+    pub fn request(&self, method: &str, url: &str) -> ureq::Request {
+        match Url::parse(url) {
+            Ok(parsed) => self.for_url(&parsed).request(method, url),
+            Err(_) => self.direct.request(method, url)
+        }
+    }
+
+    pub fn get(&self, url: &str) -> ureq::Request {
+        self.request("GET", url)
+    }
+
+    pub fn post(&self, url: &str) -> ureq::Request {
+        self.request("POST", url)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::Error;
+    use crate::testing::{Response, Server};
+
+    #[test]
+    fn sends_requests_through_proxy_unless_bypassed() {
+        let proxy_server = Server::start(|_| Response::ok("proxied"));
+        let portal = Server::start(|_| Response::ok("direct"));
+        let proxy = Proxy::default()
+            .with_url(&proxy_server.url(""))
+            .unwrap()
+            .with_bypass("127.0.0.1");
+        let agent = Transport::default().with_proxy(proxy).agent(ureq::AgentBuilder::new);
+
+        let direct = agent.get(&portal.url("/state")).call().unwrap().into_string().unwrap();
+        let proxied = agent.get("http://portal.invalid/state").call().unwrap().into_string().unwrap();
+
+        assert_eq!((direct.as_str(), proxied.as_str()), ("direct", "proxied"));
+        assert_eq!(proxy_server.requests()[0].path, "http://portal.invalid/state");
+    }
+
+    #[test]
+    fn resolves_portal_through_host_overrides() {
+        let portal = Server::start(|_| Response::ok("portal"));
+        let port = portal.url("").rsplit(':').next().unwrap().to_string();
+        let resolver = Resolver::default().with_hosts("portal.invalid=127.0.0.1").unwrap();
+        let agent = Transport::default().with_resolver(resolver).agent(ureq::AgentBuilder::new);
+
+        let url = format!("http://portal.invalid:{}/state", port);
+        assert_eq!(agent.get(&url).call().unwrap().into_string().unwrap(), "portal");
+    }
+
+    #[test]
+    fn resolution_failure_is_a_dns_error() {
+        let resolver = Resolver::default().with_hosts("other.invalid=127.0.0.1").unwrap();
+        let agent = Transport::default()
+            .with_resolver(resolver.with_nameserver("127.0.0.1:9".parse().unwrap()))
+            .agent(ureq::AgentBuilder::new);
+
+        let error = Error::from(agent.get("http://portal.invalid/").call().unwrap_err());
+        assert!(matches!(error, Error::Dns(_)), "{:?}", error);
+    }
+}
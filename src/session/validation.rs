@@ -0,0 +1,106 @@
+use lazy_regex::regex;
+use super::error::{Error, PasswordRule};
+use crate::log;
+
+/// How strictly credentials are checked before contacting the portal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Validation {
+    /// Refuse credentials that break any rule.
+    #[default]
+    Strict,
+    /// Log every broken rule but try to log in anyway.
+    Warn,
+    /// Don't check anything.
+    Off,
+}
+
+impl Validation {
+
+    pub fn check(&self, username: &str, password: &str) -> Result<(), Error> {
+        if *self == Self::Off {
+            return Ok(());
+        }
+
+        let mut errors = Self::username_errors(username)
+            .into_iter()
+            .chain(Self::password_errors(password));
+
+        match self {
+            Self::Strict => errors.next().map_or(Ok(()), Err),
+            _ => {
+                errors.for_each(|error| log::warn(&error.to_string()));
+                Ok(())
+            }
+        }
+    }
+
+    fn is_email(username: &str) -> bool {
+        regex!(r"^[a-zA-Z0-9._%+-]+@[a-zA-Z0-9.-]+\.[a-zA-Z]{2,}$").is_match(username)
+    }
+
+    /// Portuguese mobile or landline number, optionally with the country code.
+    fn is_phone_number(username: &str) -> bool {
+        let number: String = username.chars().filter(|c| !c.is_whitespace()).collect();
+        regex!(r"^(\+351|00351)?[29][0-9]{8}$").is_match(&number)
+    }
+
+    fn username_errors(username: &str) -> Option<Error> {
+        if Self::is_email(username) || Self::is_phone_number(username) {
+            None
+        } else {
+            Some(Error::InvalidUsername)
+        }
+    }
+
+    fn password_errors(password: &str) -> Vec<Error> {
+        const MIN_LENGTH: usize = 8;
+        let rules = [
+            (password.chars().count() >= MIN_LENGTH, PasswordRule::MinLength(MIN_LENGTH)),
+            (password.chars().any(|c| c.is_ascii_lowercase()), PasswordRule::Lowercase),
+            (password.chars().any(|c| c.is_ascii_uppercase()), PasswordRule::Uppercase),
+            (password.chars().any(|c| c.is_ascii_digit()), PasswordRule::Digit),
+        ];
+        rules
+            .into_iter()
+            .filter(|(passes, _)| !passes)
+            .map(|(_, rule)| Error::InvalidPassword(rule))
+            .collect()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWORD: &str = "Passw0rd";
+
+    #[test]
+    fn accepts_email_and_phone_usernames() {
+        for username in ["user@example.pt", "912345678", "+351 912 345 678", "00351212345678"] {
+            assert_eq!(Validation::Strict.check(username, PASSWORD), Ok(()), "{}", username);
+        }
+    }
+
+    #[test]
+    fn rejects_other_usernames() {
+        for username in ["user", "12345", "812345678", "+44 912345678"] {
+            assert_eq!(Validation::Strict.check(username, PASSWORD), Err(Error::InvalidUsername));
+        }
+    }
+
+    #[test]
+    fn reports_first_broken_password_rule() {
+        let check = |password| Validation::Strict.check("912345678", password);
+        assert_eq!(check("Pa55"), Err(Error::InvalidPassword(PasswordRule::MinLength(8))));
+        assert_eq!(check("PASSW0RD"), Err(Error::InvalidPassword(PasswordRule::Lowercase)));
+        assert_eq!(check("passw0rd"), Err(Error::InvalidPassword(PasswordRule::Uppercase)));
+        assert_eq!(check("Password"), Err(Error::InvalidPassword(PasswordRule::Digit)));
+    }
+
+    #[test]
+    fn lenient_policies_accept_anything() {
+        assert_eq!(Validation::Warn.check("user", "pass"), Ok(()));
+        assert_eq!(Validation::Off.check("user", "pass"), Ok(()));
+    }
+}
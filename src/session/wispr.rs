@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use lazy_regex::regex;
+use url::Url;
+use super::error::Error;
+use super::form::Form;
+use super::transport::{self, Transport};
+use super::validation::Validation;
+use crate::executor::clock::{self, Trait as Clock};
+use crate::log;
+
+/// `<WISPAccessGatewayParam>` document as embedded by the access gateway
+/// in its redirect page or returned in reply to smart-client requests.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub (super) struct Message {
+    message_type: u16,
+    pub (super) response_code: u16,
+    login_url: Option<String>,
+    abort_login_url: Option<String>,
+    pub (super) logoff_url: Option<String>,
+    login_results_url: Option<String>,
+    next_url: Option<String>,
+    delay: Option<u64>,
+    reply_message: Option<String>
+}
+
+impl Message {
+
+    const REDIRECT: u16 = 100;
+    const PROXY: u16 = 110;
+
+    pub (super) const LOGIN_SUCCEEDED: u16 = 50;
+    const LOGIN_FAILED: u16 = 100;
+    const SERVER_ERROR: u16 = 102;
+    const NO_SMART_CLIENT_SUPPORT: u16 = 105;
+    const LOGOFF_SUCCEEDED: u16 = 150;
+    const LOGIN_ABORTED: u16 = 151;
+    const AUTHENTICATION_PENDING: u16 = 201;
+    const GATEWAY_ERROR: u16 = 255;
+
+    pub (super) fn parse(text: &str) -> Option<Self> {
+        let block = regex!(r"(?s)<WISPAccessGatewayParam\b.*?</WISPAccessGatewayParam>").find(text)?;
+        let elements: HashMap<&str, String> = regex!(r"<([A-Za-z]+)>([^<]*)</([A-Za-z]+)>")
+            .captures_iter(block.as_str())
+            .filter(|c| c[1] == c[3])
+            .map(|c| (c.get(1).unwrap().as_str(), Form::decode(c[2].trim())))
+            .collect();
+        let number = |name: &str| elements.get(name).and_then(|v| v.parse().ok());
+        Some(Self {
+            message_type: number("MessageType")?,
+            response_code: number("ResponseCode")?,
+            login_url: elements.get("LoginURL").cloned(),
+            abort_login_url: elements.get("AbortLoginURL").cloned(),
+            logoff_url: elements.get("LogoffURL").cloned(),
+            login_results_url: elements.get("LoginResultsURL").cloned(),
+            next_url: elements.get("NextURL").cloned(),
+            delay: number("Delay").map(u64::from),
+            reply_message: elements.get("ReplyMessage").cloned()
+        })
+    }
+
+    pub (super) fn error(&self) -> Error {
+        let detail = self.reply_message.as_deref().unwrap_or("no details");
+        match self.response_code {
+            Self::LOGIN_FAILED => Error::CredentialsMismatch,
+            Self::SERVER_ERROR => Error::NetworkUnreachable,
+            Self::NO_SMART_CLIENT_SUPPORT => Error::Portal("gateway does not support smart clients".to_string()),
+            Self::GATEWAY_ERROR => Error::Portal(format!("access gateway internal error ({})", detail)),
+            code => Error::Portal(format!("unexpected WISPr response code {} ({})", code, detail))
+        }
+    }
+
+}
+
+/// WISPr 1.0/2.0 smart client. Instead of scraping the portal page it reads
+/// the gateway's XML parameters and talks to the login, poll, abort and
+/// logoff URLs they advertise.
+#[derive(Clone)]
+pub struct Wispr {
+    username: String,
+    password: String,
+    probe: Url,
+    agent: transport::Agent,
+    clock: Arc<dyn Clock>,
+    logoff: Arc<Mutex<Option<String>>>
+}
+
+impl std::fmt::Debug for Wispr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Wispr")
+            .field("username", &self.username)
+            .field("probe", &self.probe)
+            .field("logoff", &self.logoff)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Wispr {
+
+    const MAX_REDIRECTS: usize = 5;
+    const MAX_POLLS: usize = 5;
+    const MAX_DELAY: u64 = 30;
+
+    pub fn new(username: &str, password: &str, validation: Validation) -> Result<Self, Error> {
+        const PROBE_URL: &str = "http://connectivitycheck.gstatic.com/generate_204";
+        validation.check(username, password)?;
+        Ok(Self {
+            username: username.to_string(),
+            password: password.to_string(),
+            probe: Url::parse(PROBE_URL).expect("valid probe URL"),
+            agent: Self::agent(&Transport::default()),
+            clock: Arc::new(clock::System),
+            logoff: Arc::new(Mutex::new(None))
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn with_probe(mut self, probe: &str) -> Result<Self, Error> {
+        self.probe = Url::parse(probe).map_err(|e| Error::Portal(e.to_string()))?;
+        Ok(self)
+    }
+
+    pub fn with_transport(mut self, transport: &Transport) -> Self {
+        self.agent = Self::agent(transport);
+        self
+    }
+
+    /// Clock waited on between polls of a pending login.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    fn agent(transport: &Transport) -> transport::Agent {
+        const TIMEOUT: Duration = Duration::from_secs(5);
+        transport.agent(|| {
+            ureq::AgentBuilder::new()
+                .timeout(TIMEOUT)
+                .redirects(0)
+                .user_agent("WISPR!MEO-WiFi;")
+        })
+    }
+
+    fn get(&self, url: &str) -> Result<ureq::Response, Error> {
+        Ok(self.agent.get(url).call()?)
+    }
+
+    /// Follows redirects from the probe until a page carries WISPr data.
+    /// `None` means the probe went through, i.e. we are already online.
+    fn discover(&self) -> Result<Option<Message>, Error> {
+        let mut url = self.probe.clone();
+        for _ in 0..Self::MAX_REDIRECTS {
+            let response = self.get(url.as_str())?;
+            if response.status() == 204 {
+                return Ok(None);
+            }
+            let location = response.header("Location").map(str::to_string);
+            let body = response.into_string()?;
+            let next = match Message::parse(&body) {
+                Some(message) if message.message_type == Message::PROXY => message.next_url,
+                Some(message) => return Ok(Some(message)),
+                None => location
+            };
+            url = next
+                .and_then(|next| url.join(&next).ok())
+                .ok_or_else(|| Error::Portal("gateway sent no WISPr data".to_string()))?;
+        }
+        Err(Error::Portal("too many redirects looking for WISPr data".to_string()))
+    }
+
+    fn reply(response: ureq::Response) -> Result<Message, Error> {
+        let body = response.into_string()?;
+        Message::parse(&body).ok_or_else(|| Error::Portal("gateway sent no WISPr reply".to_string()))
+    }
+
+    /// Polls the results URL while the gateway reports authentication as
+    /// pending, honouring the advertised delay.
+    fn poll(&self, mut reply: Message) -> Result<Message, Error> {
+        for _ in 0..Self::MAX_POLLS {
+            if reply.response_code != Message::AUTHENTICATION_PENDING {
+                break;
+            }
+            let url = reply
+                .login_results_url
+                .clone()
+                .ok_or_else(|| Error::Portal("pending WISPr login without results URL".to_string()))?;
+            self.clock.sleep(Duration::from_secs(reply.delay.unwrap_or(1).min(Self::MAX_DELAY)));
+            reply = Self::reply(self.get(&url)?)?;
+        }
+        Ok(reply)
+    }
+
+    fn abort(&self, redirect: &Message) {
+        let Some(url) = &redirect.abort_login_url else {
+            return;
+        };
+        match self.get(url).and_then(Self::reply) {
+            Ok(reply) if reply.response_code == Message::LOGIN_ABORTED => log::info("WISPr login aborted"),
+            Ok(reply) => log::error(reply.error()),
+            Err(error) => log::error(error)
+        }
+    }
+
+}
+
+impl super::Trait for Wispr {
+
+    fn is_logged(&self) -> bool {
+        matches!(self.discover(), Ok(None))
+    }
+
+    fn login(&self) -> Result<(), Error> {
+        let redirect = self.discover()?.ok_or(Error::AlreadyLoggedIn)?;
+        if redirect.message_type != Message::REDIRECT {
+            return Err(redirect.error());
+        }
+        let login_url = redirect
+            .login_url
+            .as_deref()
+            .ok_or_else(|| Error::Portal("WISPr redirect without login URL".to_string()))?;
+
+        let response = self.agent.post(login_url).send_form(&[
+            ("UserName", &self.username),
+            ("Password", &self.password),
+            ("button", "Login"),
+            ("FNAME", "0"),
+            ("OriginatingServer", self.probe.as_str()),
+        ])?;
+        let reply = self.poll(Self::reply(response)?)?;
+
+        match reply.response_code {
+            Message::LOGIN_SUCCEEDED => {
+                *self.logoff.lock().unwrap() = reply.logoff_url;
+                Ok(())
+            }
+            Message::AUTHENTICATION_PENDING => {
+                self.abort(&redirect);
+                Err(Error::Portal("WISPr login still pending".to_string()))
+            }
+            _ => Err(reply.error())
+        }
+    }
+
+    fn logout(&self) -> Result<(), Error> {
+        let logoff = self.logoff.lock().unwrap().clone();
+        let url = logoff.ok_or_else(|| Error::Portal("WISPr logoff URL unknown".to_string()))?;
+        let reply = Self::reply(self.get(&url)?)?;
+        if reply.response_code != Message::LOGOFF_SUCCEEDED {
+            return Err(reply.error());
+        }
+        *self.logoff.lock().unwrap() = None;
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Trait;
+    use crate::executor::clock::Virtual;
+    use crate::testing::{Event, Journal, Response, Server};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    const REDIRECT: &str = include_str!("fixtures/wispr/redirect.html");
+    const LOGIN_SUCCESS: &str = include_str!("fixtures/wispr/login-success.xml");
+    const LOGIN_FAILED: &str = include_str!("fixtures/wispr/login-failed.xml");
+    const LOGIN_PENDING: &str = include_str!("fixtures/wispr/login-pending.xml");
+    const POLL_SUCCESS: &str = include_str!("fixtures/wispr/poll-success.xml");
+    const LOGOFF: &str = include_str!("fixtures/wispr/logoff.xml");
+    const ABORT: &str = include_str!("fixtures/wispr/abort.xml");
+
+    /// Gateway answering the login with `reply` and the results poll with
+    /// `poll`; fixtures reference it through `{base}`.
+    fn gateway(reply: &str, poll: &str) -> Server {
+        let (reply, poll) = (reply.to_string(), poll.to_string());
+        let base = Arc::new(Mutex::new(String::new()));
+        let online = Arc::new(AtomicBool::new(false));
+        let server = {
+            let base = base.clone();
+            Server::start(move |request| {
+                let fixture = |text: &str| Response::ok(&text.replace("{base}", &base.lock().unwrap()));
+                match (request.method.as_str(), request.path.as_str()) {
+                    (_, "/generate_204") if online.load(Ordering::SeqCst) => Response::status(204),
+                    (_, "/generate_204") => Response::redirect("/portal"),
+                    (_, "/portal") => fixture(REDIRECT),
+                    ("POST", path) if path.starts_with("/wispr/login") => fixture(&reply),
+                    (_, "/wispr/results") => {
+                        online.store(true, Ordering::SeqCst);
+                        fixture(&poll)
+                    }
+                    (_, "/wispr/logoff") => {
+                        online.store(false, Ordering::SeqCst);
+                        fixture(LOGOFF)
+                    }
+                    (_, "/wispr/abort") => fixture(ABORT),
+                    _ => Response::status(404)
+                }
+            })
+        };
+        *base.lock().unwrap() = server.url("");
+        server
+    }
+
+    fn session(server: &Server) -> Wispr {
+        Wispr::new("user@example.pt", "Passw0rd", Validation::Strict)
+            .and_then(|s| s.with_probe(&server.url("/generate_204")))
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_redirect_embedded_in_html_comment() {
+        let message = Message::parse(&REDIRECT.replace("{base}", "http://gw")).unwrap();
+
+        assert_eq!(message.message_type, Message::REDIRECT);
+        assert_eq!(message.response_code, 0);
+        assert_eq!(message.login_url.as_deref(), Some("http://gw/wispr/login?res=smartclient&uamip=10.0.0.1"));
+        assert_eq!(message.abort_login_url.as_deref(), Some("http://gw/wispr/abort"));
+        assert_eq!(Message::parse("<html>no xml</html>"), None);
+    }
+
+    #[test]
+    fn logs_in_and_off() {
+        let server = gateway(LOGIN_SUCCESS, POLL_SUCCESS);
+        let session = session(&server);
+
+        assert_eq!(session.login(), Ok(()));
+        let post = server.requests().into_iter().find(|r| r.method == "POST").unwrap();
+        assert!(post.body.starts_with("UserName=user%40example.pt&Password=Passw0rd&button=Login"));
+
+        assert_eq!(session.logout(), Ok(()));
+        assert!(server.requests().iter().any(|r| r.path == "/wispr/logoff"));
+    }
+
+    #[test]
+    fn polls_pending_login_until_it_succeeds() {
+        let server = gateway(LOGIN_PENDING, POLL_SUCCESS);
+        let session = session(&server);
+
+        assert_eq!(session.login(), Ok(()));
+        assert!(session.is_logged());
+    }
+
+    #[test]
+    fn waits_advertised_delay_between_polls_up_to_a_limit() {
+        let server = gateway(&LOGIN_PENDING.replace("<Delay>0</Delay>", "<Delay>90</Delay>"), POLL_SUCCESS);
+        let journal = Journal::new();
+        let session = session(&server).with_clock(Virtual::new(&journal));
+
+        assert_eq!(session.login(), Ok(()));
+        assert_eq!(journal.events(), vec![Event::Sleep(Duration::from_secs(30))]);
+    }
+
+    #[test]
+    fn aborts_login_that_stays_pending() {
+        let server = gateway(LOGIN_PENDING, LOGIN_PENDING);
+
+        assert_eq!(
+            session(&server).login(),
+            Err(Error::Portal("WISPr login still pending".to_string()))
+        );
+        assert!(server.requests().iter().any(|r| r.path == "/wispr/abort"));
+    }
+
+    #[test]
+    fn maps_response_codes_to_errors() {
+        let server = gateway(LOGIN_FAILED, POLL_SUCCESS);
+        assert_eq!(session(&server).login(), Err(Error::CredentialsMismatch));
+
+        let reply = |code| Message { response_code: code, ..Default::default() };
+        assert_eq!(reply(102).error(), Error::NetworkUnreachable);
+        assert_eq!(reply(105).error(), Error::Portal("gateway does not support smart clients".to_string()));
+        assert_eq!(reply(255).error(), Error::Portal("access gateway internal error (no details)".to_string()));
+    }
+
+    #[test]
+    fn already_online_is_reported() {
+        let server = Server::start(|_| Response::status(204));
+
+        assert_eq!(session(&server).login(), Err(Error::AlreadyLoggedIn));
+        assert!(session(&server).is_logged());
+    }
+}
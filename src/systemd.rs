@@ -0,0 +1,128 @@
+use std::path::{Path, PathBuf};
+
+const USERNAME_CREDENTIAL: &str = "meo-wifi-username";
+const PASSWORD_CREDENTIAL: &str = "meo-wifi-password";
+
+/// Username and password handed over by systemd through `LoadCredential=`.
+pub fn credentials() -> Option<(String, String)> {
+    let dir = PathBuf::from(std::env::var_os("CREDENTIALS_DIRECTORY")?);
+    let read = |name: &str| {
+        std::fs::read_to_string(dir.join(name))
+            .ok()
+            .map(|value| value.trim_end_matches(['\r', '\n']).to_string())
+    };
+    Some((read(USERNAME_CREDENTIAL)?, read(PASSWORD_CREDENTIAL)?))
+}
+
+/// Quotes `arg` for an `ExecStart=` command line.
+fn quote(arg: &str) -> String {
+    let escaped = arg.replace('\\', "\\\\").replace('"', "\\\"").replace('%', "%%").replace('$', "$$");
+    if escaped.is_empty() || escaped.contains(char::is_whitespace) || escaped != arg {
+        format!("\"{}\"", escaped)
+    } else {
+        escaped
+    }
+}
+
+/// Builds a hardened service running `exe` as a daemon with `args`, the
+/// command line it was generated from. Credentials on the command line are
+/// left out, as they are loaded from `/etc/meo-wifi` instead; without any,
+/// the accounts are expected in the configuration file.
+pub fn unit(exe: &Path, args: &[String]) -> String {
+    let credentials = if args.iter().any(|arg| arg == "-u" || arg == "--username") {
+        format!(
+            "LoadCredential={}:/etc/meo-wifi/username\nLoadCredential={}:/etc/meo-wifi/password\n",
+            USERNAME_CREDENTIAL, PASSWORD_CREDENTIAL
+        )
+    } else {
+        String::new()
+    };
+    let mut command = vec![quote(&exe.to_string_lossy()), "--daemon".to_string()];
+    command.extend(crate::args::forwarded(args).iter().map(|arg| quote(arg)));
+    command.extend([
+        "--socket".to_string(),
+        "%t/meo-wifi/meo-wifi.sock".to_string(),
+    ]);
+
+    format!(
+"[Unit]
+Description=MEO WiFi hotspot login
+Wants=NetworkManager.service
+After=NetworkManager.service network.target
+
+[Service]
+Type=notify
+NotifyAccess=main
+ExecStart={command}
+Restart=on-failure
+RestartSec=30
+WatchdogSec=180
+{credentials}Environment=XDG_CONFIG_HOME=%E XDG_STATE_HOME=%S
+RuntimeDirectory=meo-wifi
+RuntimeDirectoryMode=0700
+StateDirectory=meo-wifi
+ConfigurationDirectory=meo-wifi
+NoNewPrivileges=yes
+ProtectSystem=strict
+ReadWritePaths=/run/lock
+ProtectHome=yes
+PrivateTmp=yes
+PrivateDevices=yes
+ProtectClock=yes
+ProtectHostname=yes
+ProtectKernelTunables=yes
+ProtectKernelModules=yes
+ProtectKernelLogs=yes
+ProtectControlGroups=yes
+RestrictAddressFamilies=AF_UNIX AF_INET AF_INET6 AF_NETLINK
+RestrictNamespaces=yes
+RestrictRealtime=yes
+RestrictSUIDSGID=yes
+LockPersonality=yes
+MemoryDenyWriteExecute=yes
+SystemCallArchitectures=native
+SystemCallFilter=@system-service
+CapabilityBoundingSet=
+
+[Install]
+WantedBy=multi-user.target
+",
+        command = command.join(" "),
+        credentials = credentials
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_daemon_with_original_options_but_no_credentials() {
+        let args = ["-u", "user@meo.pt", "-p", "secret", "-c", "-t", "MEO WiFi", "--generate-systemd-unit"]
+            .map(String::from);
+
+        let unit = unit(Path::new("/usr/bin/meo-wifi"), &args);
+
+        assert!(unit.contains(
+            "ExecStart=/usr/bin/meo-wifi --daemon -t \"MEO WiFi\" --socket %t/meo-wifi/meo-wifi.sock\n"
+        ));
+        assert!(!unit.contains("secret") && !unit.contains("user@meo.pt"));
+        assert!(unit.contains("After=NetworkManager.service"));
+        assert!(unit.contains("LoadCredential=meo-wifi-password:/etc/meo-wifi/password\n"));
+    }
+
+    #[test]
+    fn leaves_credentials_to_config_when_none_given() {
+        let unit = unit(Path::new("/usr/bin/meo-wifi"), &["-t".to_string(), "MEO-WiFi".to_string()]);
+
+        assert!(!unit.contains("LoadCredential"));
+        assert!(unit.contains("Type=notify\n"));
+    }
+
+    #[test]
+    fn escapes_specifiers_and_quotes() {
+        assert_eq!(quote("50%"), "\"50%%\"");
+        assert_eq!(quote("a\"b"), "\"a\\\"b\"");
+        assert_eq!(quote("plain"), "plain");
+    }
+}
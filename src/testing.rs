@@ -0,0 +1,165 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Connect(String),
+    Disconnect(String),
+    Login,
+    Logout,
+    Sleep(Duration),
+    Notify(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Journal {
+    events: Arc<Mutex<Vec<Event>>>
+}
+
+impl Journal {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, event: Event) {
+        self.events.lock().unwrap().push(event);
+    }
+
+    pub fn events(&self) -> Vec<Event> {
+        self.events.lock().unwrap().clone()
+    }
+
+}
+
+/// Request received by a [`Server`].
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String
+}
+
+impl Request {
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+}
+
+/// Response returned by a [`Server`] handler.
+#[derive(Debug, Clone)]
+pub struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String
+}
+
+impl Response {
+
+    pub fn ok(body: &str) -> Self {
+        Self::status(200).body(body)
+    }
+
+    pub fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: String::new()
+        }
+    }
+
+    pub fn redirect(location: &str) -> Self {
+        Self::status(302).header("Location", location)
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: &str) -> Self {
+        self.body = body.to_string();
+        self
+    }
+
+}
+
+/// Minimal HTTP/1.1 server on a random local port standing in for a portal.
+/// Every request is recorded and answered by the handler.
+pub struct Server {
+    address: std::net::SocketAddr,
+    requests: Arc<Mutex<Vec<Request>>>
+}
+
+impl Server {
+
+    pub fn start(handler: impl Fn(&Request) -> Response + Send + 'static) -> Self {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Some(request) = Self::read(&stream) {
+                    let response = handler(&request);
+                    recorded.lock().unwrap().push(request);
+                    Self::write(stream, response);
+                }
+            }
+        });
+        Self { address, requests }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.address, path)
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn read(stream: &std::net::TcpStream) -> Option<Request> {
+        use std::io::{BufRead, BufReader, Read};
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let mut parts = line.split_whitespace();
+        let method = parts.next()?.to_string();
+        let path = parts.next()?.to_string();
+
+        let mut headers = Vec::new();
+        loop {
+            line.clear();
+            reader.read_line(&mut line).ok()?;
+            match line.trim_end().split_once(':') {
+                Some((name, value)) => headers.push((name.trim().to_string(), value.trim().to_string())),
+                None => break
+            }
+        }
+
+        let mut request = Request { method, path, headers, body: String::new() };
+        let length = request.header("Content-Length").and_then(|l| l.parse().ok()).unwrap_or(0);
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body).ok()?;
+        request.body = String::from_utf8_lossy(&body).to_string();
+        Some(request)
+    }
+
+    fn write(mut stream: std::net::TcpStream, response: Response) {
+        use std::io::Write;
+        let mut head = format!("HTTP/1.1 {} Status\r\n", response.status);
+        for (name, value) in &response.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n", response.body.len()));
+        let _ = stream.write_all(head.as_bytes());
+        let _ = stream.write_all(response.body.as_bytes());
+    }
+
+}